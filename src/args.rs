@@ -0,0 +1,222 @@
+use crate::Position;
+use std::path::Path;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const HELP: &str = "usage: see [options] [file...]
+
+  +<line>           start at the given line of the first file, '+' alone starts at the last line
+  <file>:<line>:<column>
+                    open a file at a line and column, like the positions printed by compilers
+  -                 read the text from the standard input
+  -R                open the files read-only
+  -o                show each file in a window of its own
+  -c <command>      run a command after the files have been opened, can be given many times
+  --pager           work as a pager, also done when started as 'view'
+  -h, --help        show this help
+  -V, --version     show the version";
+
+// A file given on the command line, together with the position to start at.
+pub struct FileArgument {
+    pub name: String,
+    pub position: Option<Position>,
+}
+
+// The options given on the command line.
+#[derive(Default)]
+pub struct Options {
+    pub files: Vec<FileArgument>, // The files to open, '-' is the standard input
+    pub read_only: bool,          // Whether editing is blocked
+    pub pager: bool,              // Whether the editor starts in the pager mode
+    pub split: bool,              // Whether every file gets a window of its own
+    pub commands: Vec<String>,    // The commands run after the files have been opened
+}
+
+// What the program should do based on its arguments.
+pub enum Action {
+    Run(Options),
+    Print(String),
+}
+
+// Parse the arguments of the program, the first argument being the name of the program.
+pub fn parse<I>(mut args: I) -> Result<Action, String>
+where
+    I: Iterator<Item = String>,
+{
+    let program = args.next().unwrap_or_default();
+    let mut options = Options {
+        pager: Path::new(&program).file_name() == Some("view".as_ref()),
+        ..Options::default()
+    };
+    // A position given with '+' applies to the file after it.
+    let mut line: Option<usize> = None;
+    let mut only_files = false;
+
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with(['-', '+']) {
+            let mut file = file_argument(&arg);
+            if let Some(line) = line.take() {
+                file.position = Some(Position { x: 0, y: line });
+            }
+            options.files.push(file);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--" => only_files = true,
+            "-R" => options.read_only = true,
+            "-o" => options.split = true,
+            "--pager" => options.pager = true,
+            "-h" | "--help" => return Ok(Action::Print(HELP.to_string())),
+            "-V" | "--version" => return Ok(Action::Print(format!("see {}", VERSION))),
+            "-c" => match args.next() {
+                Some(command) => options.commands.push(command),
+                None => return Err("-c needs a command".to_string()),
+            },
+            // '+' alone goes to the last line.
+            "+" => line = Some(usize::MAX),
+            _ => match arg.strip_prefix('+').map(str::parse::<usize>) {
+                Some(Ok(number)) => line = Some(number.saturating_sub(1)),
+                _ => return Err(format!("unknown option: {}", arg)),
+            },
+        }
+    }
+
+    // A line given after the last file applies to the first file, as in vim.
+    if let (Some(line), Some(file)) = (line, options.files.first_mut()) {
+        file.position = Some(Position { x: 0, y: line });
+    }
+    Ok(Action::Run(options))
+}
+
+// Split a position from the end of a file name, as in 'src/main.rs:10:5'. A file that exists with
+// the whole name is opened as it is.
+fn file_argument(arg: &str) -> FileArgument {
+    let whole = FileArgument {
+        name: arg.to_string(),
+        position: None,
+    };
+    if arg == "-" || Path::new(arg).exists() {
+        return whole;
+    }
+
+    let (rest, last) = match arg.rsplit_once(':') {
+        Some((rest, last)) => (rest, last),
+        None => return whole,
+    };
+    let last = match last.parse::<usize>() {
+        Ok(last) => last,
+        Err(_) => return whole,
+    };
+    let with_line = rest
+        .rsplit_once(':')
+        .and_then(|(name, line)| Some((name, line.parse::<usize>().ok()?)));
+    let (name, line, column) = match with_line {
+        Some((name, line)) => (name, line, last),
+        None => (rest, last, 1),
+    };
+    if name.is_empty() {
+        return whole;
+    }
+
+    FileArgument {
+        name: name.to_string(),
+        position: Some(Position {
+            x: column.saturating_sub(1),
+            y: line.saturating_sub(1),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(args: &[&str]) -> impl Iterator<Item = String> {
+        let args: Vec<String> = ["see"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        args.into_iter()
+    }
+
+    fn run(args: &[&str]) -> Options {
+        match parse(arguments(args)) {
+            Ok(Action::Run(options)) => options,
+            _ => panic!("expected the editor to run"),
+        }
+    }
+
+    #[test]
+    fn line_applies_to_the_next_file() {
+        let options = run(&["a.txt", "+42", "b.txt"]);
+        assert_eq!(options.files.len(), 2);
+        assert!(options.files[0].position.is_none());
+        assert_eq!(options.files[1].position, Some(Position { x: 0, y: 41 }));
+    }
+
+    #[test]
+    fn trailing_line_applies_to_the_first_file() {
+        let options = run(&["a.txt", "+"]);
+        assert_eq!(
+            options.files[0].position,
+            Some(Position {
+                x: 0,
+                y: usize::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn position_after_file_name() {
+        let options = run(&["does/not/exist.rs:10:5", "missing.rs:3"]);
+        assert_eq!(options.files[0].name, "does/not/exist.rs");
+        assert_eq!(options.files[0].position, Some(Position { x: 4, y: 9 }));
+        assert_eq!(options.files[1].name, "missing.rs");
+        assert_eq!(options.files[1].position, Some(Position { x: 0, y: 2 }));
+    }
+
+    #[test]
+    fn options() {
+        let options = run(&[
+            "-R",
+            "-o",
+            "-c",
+            "set ff=dos",
+            "-c",
+            "follow",
+            "--",
+            "-file",
+        ]);
+        assert!(options.read_only);
+        assert!(options.split);
+        assert!(!options.pager);
+        assert_eq!(options.commands, ["set ff=dos", "follow"]);
+        assert_eq!(options.files[0].name, "-file");
+    }
+
+    #[test]
+    fn pager_from_program_name() {
+        let args = ["/usr/bin/view", "-"].iter().map(|arg| arg.to_string());
+        match parse(args) {
+            Ok(Action::Run(options)) => {
+                assert!(options.pager);
+                assert_eq!(options.files[0].name, "-");
+            }
+            _ => panic!("expected the pager to run"),
+        }
+    }
+
+    #[test]
+    fn version_and_errors() {
+        assert!(matches!(
+            parse(arguments(&["--version"])),
+            Ok(Action::Print(text)) if text == format!("see {}", VERSION)
+        ));
+        assert!(matches!(parse(arguments(&["-h"])), Ok(Action::Print(text)) if text == HELP));
+        assert!(parse(arguments(&["-c"])).is_err());
+        assert!(parse(arguments(&["--nope"])).is_err());
+        assert!(parse(arguments(&["+x"])).is_err());
+    }
+}
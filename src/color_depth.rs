@@ -0,0 +1,287 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use termion::color::{self, Rgb};
+
+// The colors of the 16 color palette as xterm shows them. Terminals differ a little, but these are
+// close enough to find the nearest one.
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// The levels of each channel in the 6x6x6 color cube of the 256 color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// The index of the number of colors among the numeric capabilities of a terminfo entry.
+const MAX_COLORS: usize = 13;
+
+// The colors are drawn in this depth, which is detected when the editor starts and can be changed
+// with ':set colors=<depth>'.
+static DEPTH: AtomicU8 = AtomicU8::new(ColorDepth::TrueColor as u8);
+
+// How many colors the terminal can show.
+#[derive(PartialEq, Clone, Copy)]
+pub enum ColorDepth {
+    TrueColor, // Any RGB color
+    Ansi256,   // The 256 color palette of xterm
+    Ansi16,    // The 8 normal and 8 bright colors, which every color terminal has
+}
+
+impl ColorDepth {
+    // Find a depth by the name given to ':set colors', where "auto" detects it again.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(detect()),
+            "truecolor" | "24bit" => Some(ColorDepth::TrueColor),
+            "256" => Some(ColorDepth::Ansi256),
+            "16" | "8" => Some(ColorDepth::Ansi16),
+            _ => None,
+        }
+    }
+}
+
+pub fn set_depth(depth: ColorDepth) {
+    DEPTH.store(depth as u8, Ordering::Relaxed);
+}
+
+fn depth() -> ColorDepth {
+    match DEPTH.load(Ordering::Relaxed) {
+        depth if depth == ColorDepth::TrueColor as u8 => ColorDepth::TrueColor,
+        depth if depth == ColorDepth::Ansi256 as u8 => ColorDepth::Ansi256,
+        _ => ColorDepth::Ansi16,
+    }
+}
+
+// Find how many colors the terminal can show. COLORTERM tells about true color, and otherwise the
+// number of colors is read from the terminfo entry of the terminal. A terminal that can't be
+// found gets the 16 colors, which work almost everywhere.
+pub fn detect() -> ColorDepth {
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    if colorterm == "truecolor" || colorterm == "24bit" {
+        return ColorDepth::TrueColor;
+    }
+
+    let term = env::var("TERM").unwrap_or_default();
+    if term.ends_with("-direct") || term.contains("truecolor") || term.contains("24bit") {
+        return ColorDepth::TrueColor;
+    }
+    match terminfo_colors(&term) {
+        Some(colors) if colors >= 1 << 24 => ColorDepth::TrueColor,
+        Some(colors) if colors >= 256 => ColorDepth::Ansi256,
+        Some(_) => ColorDepth::Ansi16,
+        None if term.contains("256color") => ColorDepth::Ansi256,
+        None => ColorDepth::Ansi16,
+    }
+}
+
+// Return the escape code that sets the text color, using the nearest color the terminal has.
+pub fn foreground(rgb: Rgb) -> String {
+    match depth() {
+        ColorDepth::TrueColor => color::Fg(rgb).to_string(),
+        ColorDepth::Ansi256 => color::Fg(color::AnsiValue(nearest_256(rgb))).to_string(),
+        ColorDepth::Ansi16 => ansi_escape(nearest_16(rgb), 30, 90),
+    }
+}
+
+// Return the escape code that sets the background color, using the nearest color the terminal
+// has.
+pub fn background(rgb: Rgb) -> String {
+    match depth() {
+        ColorDepth::TrueColor => color::Bg(rgb).to_string(),
+        ColorDepth::Ansi256 => color::Bg(color::AnsiValue(nearest_256(rgb))).to_string(),
+        ColorDepth::Ansi16 => ansi_escape(nearest_16(rgb), 40, 100),
+    }
+}
+
+// The 16 colors are set with their own codes, since the consoles that only have them don't
+// understand the codes of the 256 color palette.
+fn ansi_escape(index: u8, normal: u8, bright: u8) -> String {
+    if index < 8 {
+        format!("\x1b[{}m", normal + index)
+    } else {
+        format!("\x1b[{}m", bright + index - 8)
+    }
+}
+
+// Find the nearest color of the 256 color palette among the color cube and the grays. The first
+// 16 colors are left out, since terminals show them differently.
+fn nearest_256(rgb: Rgb) -> u8 {
+    let level = |channel: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|index| CUBE_LEVELS[*index].abs_diff(channel))
+            .unwrap_or(0)
+    };
+    let (red, green, blue) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (CUBE_LEVELS[red], CUBE_LEVELS[green], CUBE_LEVELS[blue]);
+
+    // The grays go from 8 to 238 in steps of 10.
+    let average = (rgb.0 as usize + rgb.1 as usize + rgb.2 as usize) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23);
+    let gray = (8 + gray_index * 10) as u8;
+
+    if distance(rgb, (gray, gray, gray)) < distance(rgb, cube) {
+        232 + gray_index as u8
+    } else {
+        16 + (36 * red + 6 * green + blue) as u8
+    }
+}
+
+fn nearest_16(rgb: Rgb) -> u8 {
+    (0..ANSI_COLORS.len())
+        .min_by_key(|index| distance(rgb, ANSI_COLORS[*index]))
+        .unwrap_or(0) as u8
+}
+
+fn distance(rgb: Rgb, (red, green, blue): (u8, u8, u8)) -> u32 {
+    let square = |a: u8, b: u8| (a.abs_diff(b) as u32).pow(2);
+    square(rgb.0, red) + square(rgb.1, green) + square(rgb.2, blue)
+}
+
+// Read the number of colors from the terminfo entry of the terminal, looking in the same
+// directories as ncurses.
+fn terminfo_colors(term: &str) -> Option<i32> {
+    let first = term.chars().next()?;
+    let mut directories: Vec<PathBuf> = Vec::new();
+    directories.extend(env::var_os("TERMINFO").map(PathBuf::from));
+    directories.extend(env::var_os("HOME").map(|home| PathBuf::from(home).join(".terminfo")));
+    if let Some(list) = env::var_os("TERMINFO_DIRS") {
+        directories.extend(env::split_paths(&list).filter(|path| !path.as_os_str().is_empty()));
+    }
+    directories
+        .extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+
+    // The entries are in a directory named after their first letter, or its hex code on macOS.
+    let subdirectories = [first.to_string(), format!("{:x}", first as u32)];
+    let entry = directories.iter().find_map(|directory| {
+        subdirectories
+            .iter()
+            .find_map(|subdirectory| fs::read(directory.join(subdirectory).join(term)).ok())
+    })?;
+    max_colors(&entry)
+}
+
+// Read the number of colors from a compiled terminfo entry. The header tells the sizes of the
+// names and the booleans, which the numbers come after, and whether the numbers have 16 or 32
+// bits.
+fn max_colors(entry: &[u8]) -> Option<i32> {
+    let short = |index: usize| {
+        let bytes = entry.get(index * 2..index * 2 + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let number_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let (names, booleans, numbers) = (short(1)?, short(2)?, short(3)?);
+    if numbers <= MAX_COLORS {
+        return None;
+    }
+
+    // The numbers start at an even byte.
+    let start = 12 + names + booleans;
+    let start = start + start % 2 + MAX_COLORS * number_size;
+    let bytes = entry.get(start..start + number_size)?;
+    let colors = match bytes {
+        [low, high] => i16::from_le_bytes([*low, *high]) as i32,
+        [a, b, c, d] => i32::from_le_bytes([*a, *b, *c, *d]),
+        _ => return None,
+    };
+    // A missing capability is written as a negative number.
+    (colors >= 0).then_some(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a compiled terminfo entry with the given numbers, in the legacy format with 16 bit
+    // numbers or the extended one with 32 bit numbers.
+    fn entry(names: &str, booleans: usize, numbers: &[i32], wide: bool) -> Vec<u8> {
+        let magic: u16 = if wide { 0o1036 } else { 0o432 };
+        let mut bytes = Vec::new();
+        for short in [
+            magic,
+            names.len() as u16 + 1,
+            booleans as u16,
+            numbers.len() as u16,
+            0,
+            0,
+        ] {
+            bytes.extend(short.to_le_bytes());
+        }
+        bytes.extend(names.as_bytes());
+        bytes.push(0);
+        bytes.extend(vec![1; booleans]);
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        for number in numbers {
+            if wide {
+                bytes.extend(number.to_le_bytes());
+            } else {
+                bytes.extend((*number as i16).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn numbers(colors: i32) -> Vec<i32> {
+        let mut numbers = vec![-1; MAX_COLORS + 3];
+        numbers[MAX_COLORS] = colors;
+        numbers
+    }
+
+    #[test]
+    fn legacy_format() {
+        let xterm = entry(
+            "xterm-256color|xterm with 256 colors",
+            38,
+            &numbers(256),
+            false,
+        );
+        assert_eq!(max_colors(&xterm), Some(256));
+        // The names and booleans have an odd length here, so the numbers start one byte later.
+        let vt = entry("vt", 4, &numbers(8), false);
+        assert_eq!(max_colors(&vt), Some(8));
+    }
+
+    #[test]
+    fn extended_format() {
+        let direct = entry("xterm-direct", 40, &numbers(1 << 24), true);
+        assert_eq!(max_colors(&direct), Some(1 << 24));
+    }
+
+    #[test]
+    fn missing_colors() {
+        assert_eq!(max_colors(&entry("dumb", 2, &numbers(-1), false)), None);
+        // An entry without as many numbers doesn't have the capability at all.
+        assert_eq!(max_colors(&entry("short", 2, &[80, 24], false)), None);
+    }
+
+    #[test]
+    fn invalid_entry() {
+        assert_eq!(max_colors(&[]), None);
+        let mut bad_magic = entry("xterm", 2, &numbers(256), false);
+        bad_magic[0] = 0;
+        assert_eq!(max_colors(&bad_magic), None);
+        let truncated = entry("xterm", 2, &numbers(256), false);
+        assert_eq!(max_colors(&truncated[..40]), None);
+    }
+}
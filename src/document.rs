@@ -1,56 +1,759 @@
+use crate::encoding;
+use crate::hex;
+use crate::highlighting::State;
+use crate::large::{self, LargeFile};
+#[cfg(feature = "tree-sitter")]
+use crate::syntax_tree::SyntaxTree;
+use crate::textmate::Highlighter;
 use crate::FileType;
 use crate::Position;
 use crate::Row;
 use crate::SearchDirection;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use std::cmp;
+use std::ffi::CString;
 use std::fs;
-use std::io::{Error, Write};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::Range;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+// How many rows are highlighted at most each time the screen is drawn. The rows above the view
+// are highlighted this many at a time, so that jumping to the end of a long file doesn't freeze
+// the editor.
+const HIGHLIGHT_BATCH: usize = 10_000;
+
+// The line break used in a file.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum LineEnding {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+        }
+    }
+
+    // Find the line ending based on the first line break of the content.
+    fn detect(content: &str) -> Self {
+        match content.find('\n') {
+            Some(index) if content[..index].ends_with('\r') => LineEnding::Dos,
+            _ => LineEnding::Unix,
+        }
+    }
+
+    // Return true if the content has lines ending in both kinds of line breaks.
+    fn is_mixed(content: &str) -> bool {
+        let dos = content.matches("\r\n").count();
+        dos > 0 && dos < content.matches('\n').count()
+    }
+}
+
+// What a file looked like on the disk, used to notice when another program changes it.
+#[derive(PartialEq, Clone, Copy)]
+struct DiskState {
+    modified: SystemTime,
+    len: u64,
+    inode: u64, // Changes when the file is replaced by a new one, as editors often save
+}
+
+impl DiskState {
+    fn read(file_name: &str) -> Option<Self> {
+        let metadata = fs::metadata(file_name).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+            inode: metadata.ino(),
+        })
+    }
+}
 
-#[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
     file_type: FileType,
+    file_type_set: bool, // Whether the filetype was set with ':set filetype', which keeps it
     edited: bool,
+    line_ending: LineEnding,       // The line break written between rows
+    mixed_line_endings: bool,      // Whether the file has both line breaks, saving unifies them
+    final_newline: bool,           // Whether the last row ends with a line break
+    bom: bool,                     // Whether the file starts with a byte order mark
+    encoding: &'static Encoding,   // The encoding the file is read and saved in
+    bytes: Option<Vec<u8>>,        // The content of a binary file, which the rows show in hex
+    disk_state: Option<DiskState>, // The file on the disk when it was last read or written
+    large: Option<LargeFile>,      // A large file, of which only some rows are loaded at a time
+    first_row: usize,              // The line of the first loaded row of a large file
+    following: bool,               // Whether lines added to the file are read as they appear
+    decoder: Option<Decoder>,      // Decodes the added bytes, keeping a character split by a read
+    stdin: bool,                   // Whether the content is read from the standard input
+    read_only: bool,               // Whether editing and saving without ':w!' are blocked
+    highlighted: usize,            // The rows before this one are highlighted
+    word: Option<String>,          // The searched word, whose matches are highlighted
+    grammar: Option<Highlighter>,  // Highlights the rows if the filetype is a TextMate grammar
+    // The tree the rows are highlighted from, if the filetype has a tree-sitter grammar.
+    #[cfg(feature = "tree-sitter")]
+    syntax_tree: Option<SyntaxTree>,
 }
 
 impl Document {
     // open returns a document based on a filename that is given as a parameter.
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        // Read the content of a file to a string.
-        let content = fs::read_to_string(filename)?;
+        Document::open_with_encoding(filename, None)
+    }
 
-        // Find the correct file type. If the filetype is not found, then we just set a default
-        // file type for the document.
-        let file_type = FileType::from(filename);
+    // Open a document using the given encoding. Without an encoding, the encoding is detected and
+    // binary files are opened in the hex view.
+    pub fn open_with_encoding(
+        filename: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<Self, std::io::Error> {
+        let disk_state = DiskState::read(filename);
+        if disk_state.is_some_and(|state| large::is_large(state.len)) {
+            return Document::open_large(filename, disk_state);
+        }
 
-        // Go through the lines in the document.
-        let mut rows = Vec::new();
-        for value in content.lines() {
-            let mut row = Row::from(value);
-            row.highlight(&file_type.highlight_options(), None);
-            rows.push(row);
+        let bytes = fs::read(filename)?;
+        if encoding.is_none() && encoding::is_binary(&bytes) {
+            return Ok(Self {
+                rows: hex::dump_rows(&bytes),
+                bytes: Some(bytes),
+                disk_state,
+                read_only: !is_writable(filename),
+                ..Document::default(filename)
+            });
         }
 
+        // Remember how the file was stored, so that saving it writes it back the same way.
+        let decoded = encoding::decode(&bytes, encoding);
+        let content = &decoded.text;
+
+        // Find the correct file type. If the filetype is not found, then we just set a default
+        // file type for the document.
+        let file_type = FileType::from(filename, content);
+
         Ok(Self {
-            rows,
+            rows: Document::rows_from(content),
             file_name: Some(filename.to_string()),
+            #[cfg(feature = "tree-sitter")]
+            syntax_tree: SyntaxTree::new(&file_type.name()),
+            grammar: file_type.grammar().map(Highlighter::new),
             file_type,
+            file_type_set: false,
             edited: false,
+            line_ending: LineEnding::detect(content),
+            mixed_line_endings: LineEnding::is_mixed(content),
+            final_newline: content.ends_with('\n'),
+            bom: decoded.bom,
+            encoding: decoded.encoding,
+            bytes: None,
+            disk_state,
+            large: None,
+            first_row: 0,
+            following: false,
+            decoder: None,
+            stdin: false,
+            read_only: !is_writable(filename),
+            highlighted: 0,
+            word: None,
         })
     }
 
+    // Open a large file without reading it. The rows are loaded when they are shown, and the text
+    // is always read as UTF-8.
+    fn open_large(filename: &str, disk_state: Option<DiskState>) -> Result<Self, Error> {
+        let mut file = LargeFile::open(filename)?;
+        let first_line = file.line(0).unwrap_or_default();
+        let line_ending = if file.is_dos() {
+            LineEnding::Dos
+        } else {
+            LineEnding::Unix
+        };
+        let file_type = FileType::from(filename, &first_line);
+
+        Ok(Self {
+            rows: Document::rows_from(&first_line),
+            grammar: file_type.grammar().map(Highlighter::new),
+            file_type,
+            line_ending,
+            final_newline: file.ends_with_newline(),
+            disk_state,
+            large: Some(file),
+            // Only part of a large file is ever loaded, so it can't be edited.
+            read_only: true,
+            ..Document::default(filename)
+        })
+    }
+
+    // Make sure the rows from the given line on are loaded, so that a screen of rows can be shown.
+    // The rows around them are loaded too, so that moving a little doesn't load everything again.
+    pub fn load_rows(&mut self, first: usize, count: usize) {
+        let file = match self.large.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+
+        // Find the lines of the next screen too, so that the cursor can move there.
+        file.index_to(first.saturating_add(count.saturating_mul(2)));
+        let wanted_end = cmp::min(first.saturating_add(count), file.lines());
+        let loaded_end = self.first_row + self.rows.len();
+        if first >= self.first_row && wanted_end <= loaded_end {
+            return;
+        }
+
+        self.first_row = first.saturating_sub(count);
+        let end = cmp::min(first.saturating_add(count.saturating_mul(2)), file.lines());
+        self.rows = (self.first_row..end)
+            .filter_map(|index| file.line(index))
+            .map(|line| Row::from(&line[..]))
+            .collect();
+        // The rows before the loaded ones aren't known, so the first row starts in the normal
+        // state even if it is inside a block comment.
+        self.highlighted = 0;
+    }
+
+    // Return true if the document is a large file that is loaded a part at a time.
+    pub fn is_large(&self) -> bool {
+        self.large.is_some()
+    }
+
+    // Return true once the number of lines of a large file is known.
+    pub fn is_fully_loaded(&self) -> bool {
+        self.large.as_ref().is_none_or(LargeFile::is_indexed)
+    }
+
+    // Large files and the hex view can't be edited as text.
+    fn is_text(&self) -> bool {
+        !self.is_hex() && !self.is_large()
+    }
+
+    // Return true if the text of the document can be changed.
+    fn is_editable_text(&self) -> bool {
+        self.is_text() && !self.read_only
+    }
+
+    // Split the content into rows, which are highlighted once they are shown.
+    fn rows_from(content: &str) -> Vec<Row> {
+        content.lines().map(Row::from).collect()
+    }
+
+    // Highlight the rows from the given line on that fit on the screen, and find the matches of
+    // the searched word in them. The rows above are highlighted first if they haven't been, since
+    // a comment opened there can go on into the shown rows, but the rows below are left until they
+    // are shown. A row is highlighted again only if it has changed or starts in another state, so
+    // typing inside a row doesn't highlight the rest of the document. Returns true if the rows
+    // above were too many to highlight at once, in which case the shown rows are highlighted as if
+    // nothing was open above them until this is called again enough times.
+    pub fn highlight_rows(&mut self, first: usize, count: usize) -> bool {
+        // The hex view has no syntax to highlight, and the matches are bytes instead of words.
+        if self.is_hex() {
+            return false;
+        }
+
+        let first = first.saturating_sub(self.first_row);
+        let end = cmp::min(first.saturating_add(count), self.rows.len());
+        let first = cmp::min(first, end);
+        #[cfg(feature = "tree-sitter")]
+        let unfinished = if let Some(tree) = self.syntax_tree.as_mut() {
+            tree.highlight_rows(&mut self.rows, first..end);
+            false
+        } else {
+            self.highlight_until(first, end)
+        };
+        #[cfg(not(feature = "tree-sitter"))]
+        let unfinished = self.highlight_until(first, end);
+
+        for row in &mut self.rows[first..end] {
+            row.highlight_match(self.word.as_deref());
+        }
+        unfinished
+    }
+
+    // Highlight the rows up to the given one, carrying the state of each row over to the next. The
+    // rows are highlighted by the grammar of the filetype if it has one, and otherwise by its
+    // keywords. At most a batch of rows is highlighted, and if the shown rows from first to end
+    // aren't reached, they are highlighted on their own and true is returned.
+    fn highlight_until(&mut self, first: usize, end: usize) -> bool {
+        let stop = cmp::min(end, self.highlighted.saturating_add(HIGHLIGHT_BATCH));
+        if let Some(grammar) = self.grammar.as_mut() {
+            if self.highlighted < stop {
+                grammar.highlight_rows(&mut self.rows, self.highlighted..stop);
+            }
+        } else if self.highlighted < stop {
+            let state = match self.highlighted.checked_sub(1) {
+                Some(index) => self.rows[index].end_state(),
+                None => State::Normal,
+            };
+            self.highlight_keywords(self.highlighted..stop, state);
+        }
+        self.highlighted = cmp::max(self.highlighted, stop);
+        if stop == end {
+            return false;
+        }
+
+        let shown = cmp::max(first, stop)..end;
+        match self.file_type.grammar() {
+            Some(grammar) => {
+                let len = shown.len();
+                Highlighter::new(grammar).highlight_rows(&mut self.rows[shown], 0..len);
+            }
+            None => self.highlight_keywords(shown, State::Normal),
+        }
+        true
+    }
+
+    // Highlight the rows by the keywords of the filetype, the first one starting in the given
+    // state.
+    fn highlight_keywords(&mut self, rows: Range<usize>, start: State) {
+        let options = self.file_type.highlight_options();
+        let mut state = start;
+        for row in &mut self.rows[rows] {
+            if !row.is_highlighted_from(state) {
+                row.highlight(options, state);
+            }
+            state = row.end_state();
+        }
+    }
+
+    // Note that the rows from the given one on have changed, so they are checked again the next
+    // time they are shown.
+    fn rows_changed(&mut self, from: usize) {
+        self.highlighted = cmp::min(self.highlighted, from);
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = self.syntax_tree.as_mut() {
+            tree.changed();
+        }
+    }
+
+    // Highlight every row again, for example after the filetype has changed. The syntax tree is
+    // parsed again with the grammar of the filetype.
+    fn reset_highlighting(&mut self) {
+        for row in &mut self.rows {
+            row.reset_highlighting();
+        }
+        self.highlighted = 0;
+        self.grammar = self.file_type.grammar().map(Highlighter::new);
+        #[cfg(feature = "tree-sitter")]
+        if self.is_text() {
+            self.syntax_tree = SyntaxTree::new(&self.file_type.name());
+        }
+    }
+
+    // Replace the whole content of the document, for example with the content of a swap file. The
+    // hex view takes the content as its bytes, and the text is read from UTF-8.
+    pub fn replace_contents(&mut self, content: &[u8]) {
+        self.large = None;
+        self.first_row = 0;
+        if let Some(bytes) = self.bytes.as_mut() {
+            *bytes = content.to_vec();
+            self.rows = hex::dump_rows(content);
+        } else {
+            self.rows = Document::rows_from(&String::from_utf8_lossy(content));
+        }
+        self.rows_changed(0);
+        self.edited = true;
+    }
+
+    // Return the bytes shown in the hex view, or the text of the document in UTF-8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.bytes {
+            Some(bytes) => bytes.clone(),
+            None => self.contents().into_bytes(),
+        }
+    }
+
+    // Return the content of the document in the same format it is saved in. A large file gives only
+    // its loaded rows, write_to copies all of it.
+    pub fn contents(&self) -> String {
+        let mut content = String::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            content.push_str(&String::from_utf8_lossy(row.as_bytes()));
+            if index + 1 < self.rows.len() || self.final_newline {
+                content.push_str(self.line_ending.as_str());
+            }
+        }
+        content
+    }
+
+    // Write the content of the document in UTF-8, copying a large file from the disk a part at a
+    // time instead of reading all of it into memory.
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        match &self.large {
+            Some(file) => file.copy_to(writer),
+            None => writer.write_all(&self.to_bytes()),
+        }
+    }
+
     pub fn default(file_name: &str) -> Self {
         Document {
             edited: false,
             file_type: FileType::default(),
+            file_type_set: false,
             rows: Vec::new(),
             file_name: Some(file_name.to_string()),
+            line_ending: LineEnding::default(),
+            mixed_line_endings: false,
+            final_newline: true,
+            bom: false,
+            encoding: UTF_8,
+            bytes: None,
+            disk_state: None,
+            large: None,
+            first_row: 0,
+            following: false,
+            decoder: None,
+            stdin: false,
+            read_only: false,
+            highlighted: 0,
+            word: None,
+            grammar: None,
+            #[cfg(feature = "tree-sitter")]
+            syntax_tree: None,
+        }
+    }
+
+    // Create a document without a file, for example to show the output of a command.
+    pub fn from_text(content: &str) -> Self {
+        Document {
+            rows: Document::rows_from(content),
+            file_name: None,
+            ..Document::default("")
+        }
+    }
+
+    // Create an empty document for the text read from the standard input. It is read-only, since
+    // more text may still be added to its end.
+    pub fn from_stdin() -> Self {
+        Document {
+            stdin: true,
+            read_only: true,
+            ..Document::from_text("")
+        }
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        self.stdin
+    }
+
+    // Read the document again from the disk, keeping the encoding it was opened with.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let file_name = self.file_name.clone().unwrap_or_default();
+        let encoding = if self.is_hex() {
+            None
+        } else {
+            Some(self.encoding)
+        };
+        let following = self.following;
+        let read_only = self.read_only;
+        let file_type = self.file_type_set.then(|| mem::take(&mut self.file_type));
+        *self = Document::open_with_encoding(&file_name, encoding)?;
+        self.following = following;
+        self.read_only |= read_only;
+        if let Some(file_type) = file_type {
+            self.file_type = file_type;
+            self.file_type_set = true;
+            self.reset_highlighting();
+        }
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // Large files stay read-only, since only a part of them is loaded.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only || self.is_large();
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
+
+    // Start or stop following the lines added to the file. The hex view can't be followed.
+    pub fn set_following(&mut self, following: bool) -> Result<(), Error> {
+        if following && self.is_hex() {
+            return Err(Error::other("binary files can't be followed"));
+        }
+        self.following = following;
+        Ok(())
+    }
+
+    // Read the bytes that have been added to the end of the file since it was last read, and add
+    // them as rows. Returns false if the file has changed in some other way, for example if it was
+    // truncated, replaced or written again with the same length, in which case it has to be read
+    // again as a whole.
+    pub fn read_appended(&mut self) -> Result<bool, Error> {
+        let file_name = self.file_name.clone().unwrap_or_default();
+        let (old, new) = match (self.disk_state, DiskState::read(&file_name)) {
+            (Some(old), Some(new)) if new.inode == old.inode && new.len > old.len => (old, new),
+            (Some(old), Some(new)) if old == new => return Ok(true),
+            _ => return Ok(false),
+        };
+        if let Some(file) = self.large.as_mut() {
+            file.extend(&file_name)?;
+            self.final_newline = file.ends_with_newline();
+            // The last loaded row may have grown, so the rows are loaded again.
+            self.rows.clear();
+            self.disk_state = Some(new);
+            return Ok(true);
+        }
+
+        let mut bytes = Vec::new();
+        let mut file = fs::File::open(&file_name)?;
+        file.seek(SeekFrom::Start(old.len))?;
+        file.read_to_end(&mut bytes)?;
+        // A character may be split between two reads, the decoder keeps its first bytes until the
+        // rest of it is read.
+        let encoding = self.encoding;
+        let decoder = self
+            .decoder
+            .get_or_insert_with(|| encoding.new_decoder_without_bom_handling());
+        let mut text = String::with_capacity(
+            decoder
+                .max_utf8_buffer_length(bytes.len())
+                .unwrap_or(bytes.len()),
+        );
+        let _ = decoder.decode_to_string(&bytes, &mut text, false);
+        self.append_text(&text);
+        self.disk_state = Some(new);
+        Ok(true)
+    }
+
+    // Add text to the end of the document without counting it as an edit.
+    pub fn append_text(&mut self, text: &str) {
+        // Line breaks of another kind than the rows had so far make the line endings mixed.
+        let breaks = text.matches('\n').count();
+        let dos = text.matches("\r\n").count();
+        if self.rows.is_empty() && breaks > 0 {
+            self.line_ending = LineEnding::detect(text);
+        }
+        match self.line_ending {
+            LineEnding::Unix if dos > 0 => self.mixed_line_endings = true,
+            LineEnding::Dos if dos < breaks => self.mixed_line_endings = true,
+            _ => {}
+        }
+
+        // The first part finishes the last row if it didn't end with a line break yet.
+        let mut lines = text.split_inclusive('\n');
+        let mut first = self.rows.len();
+        if !self.final_newline && !self.rows.is_empty() {
+            if let (Some(row), Some(line)) = (self.rows.last_mut(), lines.next()) {
+                row.append(&Row::from(line.trim_end_matches(['\r', '\n'])));
+                first -= 1;
+            }
+        }
+        for line in lines {
+            self.rows
+                .push(Row::from(line.trim_end_matches(['\r', '\n'])));
+        }
+        self.rows_changed(first);
+        if !text.is_empty() {
+            self.final_newline = text.ends_with('\n');
         }
     }
 
+    // Return the number of lines, reading through the whole file if it is a large file.
+    pub fn count_lines(&mut self) -> usize {
+        if let Some(file) = self.large.as_mut() {
+            file.index_to(usize::MAX);
+        }
+        self.len()
+    }
+
+    // Return true if another program has changed the file since the document read or wrote it.
+    // A file that has been removed doesn't count, saving the document just creates it again.
+    pub fn changed_on_disk(&self) -> bool {
+        let file_name = match &self.file_name {
+            Some(file_name) => file_name,
+            None => return false,
+        };
+        match DiskState::read(file_name) {
+            Some(current) => Some(current) != self.disk_state,
+            None => false,
+        }
+    }
+
+    // Accept the file on the disk as it is now, so that it no longer counts as changed.
+    pub fn ignore_disk_changes(&mut self) {
+        self.disk_state = self.file_name.as_deref().and_then(DiskState::read);
+    }
+
+    // Return true if the document is a binary file shown in the hex view.
+    pub fn is_hex(&self) -> bool {
+        self.bytes.is_some()
+    }
+
+    // Return the number of bytes shown in the hex view.
+    pub fn hex_len(&self) -> usize {
+        self.bytes.as_ref().map_or(0, Vec::len)
+    }
+
+    // Switch between the hex view and the text. The text is turned into the bytes it would be saved
+    // as, and the bytes are read back using the encoding of the document.
+    pub fn toggle_hex(&mut self) -> Result<(), Error> {
+        if self.is_large() {
+            return Err(Error::other("large files can't be shown in hex"));
+        }
+
+        match self.bytes.take() {
+            Some(bytes) => {
+                let decoded = encoding::decode(&bytes, Some(self.encoding));
+                let content = &decoded.text;
+                self.rows = Document::rows_from(content);
+                self.rows_changed(0);
+                self.line_ending = LineEnding::detect(content);
+                self.mixed_line_endings = LineEnding::is_mixed(content);
+                self.final_newline = content.ends_with('\n');
+                self.bom = decoded.bom;
+            }
+            None => {
+                let bytes = encoding::encode(&self.contents(), self.encoding, self.bom)?;
+                self.rows = hex::dump_rows(&bytes);
+                self.bytes = Some(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    // Overwrite the nibble at a position of the hex view with a new value.
+    pub fn set_nibble(&mut self, at: &Position, value: u8) {
+        if self.read_only {
+            return;
+        }
+        let bytes = match self.bytes.as_mut() {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        let nibble = hex::nibble_at_column(at.x);
+        let row_start = at.y * hex::BYTES_PER_ROW;
+        let offset = row_start + nibble / 2;
+        if offset >= bytes.len() {
+            return;
+        }
+
+        bytes[offset] = if nibble.is_multiple_of(2) {
+            (bytes[offset] & 0x0f) | (value << 4)
+        } else {
+            (bytes[offset] & 0xf0) | (value & 0x0f)
+        };
+        let row_end = cmp::min(row_start + hex::BYTES_PER_ROW, bytes.len());
+        self.rows[at.y] = Row::from(&hex::dump_line(row_start, &bytes[row_start..row_end])[..]);
+        self.edited = true;
+    }
+
+    // Find a sequence of bytes in the hex view, the query is given in hex or as text. Returns the
+    // position of the first nibble of the match.
+    fn find_bytes(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<Position> {
+        let bytes = self.bytes.as_ref()?;
+        let needle = hex::parse_bytes(query).unwrap_or_else(|| query.as_bytes().to_vec());
+        let nibble = hex::nibble_at_column(at.x);
+        let current = at.y * hex::BYTES_PER_ROW + nibble / 2;
+        if needle.is_empty() || needle.len() > bytes.len() {
+            return None;
+        }
+
+        let mut candidates = 0..=bytes.len() - needle.len();
+        let offset = if direction == SearchDirection::Forward {
+            // Continue after the current byte if the cursor has moved away from its start.
+            let start = if nibble.is_multiple_of(2) {
+                current
+            } else {
+                current + 1
+            };
+            candidates.find(|offset| *offset >= start && bytes[*offset..].starts_with(&needle))
+        } else {
+            candidates.rfind(|offset| *offset < current && bytes[*offset..].starts_with(&needle))
+        }?;
+
+        Some(Position {
+            x: hex::nibble_column((offset % hex::BYTES_PER_ROW) * 2),
+            y: offset / hex::BYTES_PER_ROW,
+        })
+    }
+
+    // Change the encoding that is used when the document is saved.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if self.encoding != encoding && self.is_text() {
+            self.encoding = encoding;
+            self.decoder = None;
+            self.edited = true;
+        }
+    }
+
+    // Describe the line ending, and whether the file lacks the final newline or has a byte order
+    // mark.
+    pub fn file_format(&self) -> String {
+        if self.is_hex() {
+            return "binary".to_string();
+        }
+
+        let mut format = self.line_ending.name().to_string();
+        if self.mixed_line_endings {
+            format.push_str(" mixed");
+        }
+        if self.encoding != UTF_8 {
+            format.push(' ');
+            format.push_str(&self.encoding.name().to_lowercase());
+        }
+        if !self.final_newline {
+            format.push_str(" noeol");
+        }
+        if self.bom {
+            format.push_str(" bom");
+        }
+        if self.is_large() {
+            format.push_str(" large");
+        }
+        format
+    }
+
+    // Change the line ending that is used when the document is saved. Choosing one for a file
+    // with mixed line endings accepts that every line gets it.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if !self.is_text() {
+            return;
+        }
+        if self.line_ending != line_ending || self.mixed_line_endings {
+            self.edited = true;
+        }
+        self.line_ending = line_ending;
+        self.mixed_line_endings = false;
+    }
+
+    // Return true if the file has lines ending in both kinds of line breaks. The rows don't keep
+    // their own line breaks, so saving the document writes the same one after every row.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
     // Returns a reference to row at index
     pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+        self.rows.get(index.checked_sub(self.first_row)?)
     }
 
     // Returns true if the current document is edited, and false if not.
@@ -60,12 +763,16 @@ impl Document {
 
     // Return a boolean value about if the document is open or not.
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
 
-    // Return the amount of rows in a document.
+    // Return the amount of rows in a document. For a large file this is the number of lines found
+    // so far.
     pub fn len(&self) -> usize {
-        self.rows.len()
+        match &self.large {
+            Some(file) => file.lines(),
+            None => self.rows.len(),
+        }
     }
 
     // Return the document's filetype's name..
@@ -73,12 +780,21 @@ impl Document {
         self.file_type.name()
     }
 
+    // Use the filetype with the given name instead of the detected one.
+    pub fn set_file_type(&mut self, name: &str) -> Result<(), Error> {
+        self.file_type = FileType::named(name)
+            .ok_or_else(|| Error::other(format!("unknown filetype: {}", name)))?;
+        self.file_type_set = true;
+        self.reset_highlighting();
+        Ok(())
+    }
+
     // delete handles the deletion of a character at a given position.
     pub fn delete(&mut self, at: &Position) {
         let len = self.len();
 
-        // We can't remove a character that isn't there.
-        if at.y >= len {
+        // We can't remove a character that isn't there. The hex view can't be edited as text.
+        if at.y >= len || !self.is_editable_text() {
             return;
         }
 
@@ -86,17 +802,39 @@ impl Document {
             let next_row = self.rows.remove(at.y + 1);
             let row = self.rows.get_mut(at.y).unwrap();
             row.append(&next_row);
-            row.highlight(&self.file_type.highlight_options(), None);
         } else {
             let row = self.rows.get_mut(at.y).unwrap();
             row.delete(at.x);
-            row.highlight(&self.file_type.highlight_options(), None);
+        }
+        self.rows_changed(at.y);
+    }
+
+    // Delete everything between two positions, the character at the end position is kept.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        if start.y >= self.len() || !self.is_editable_text() {
+            return;
+        }
+
+        // Count the characters between the positions, each line break counts as one.
+        let mut count = 0;
+        let mut y = start.y;
+        let mut x = start.x;
+        while y < end.y {
+            count += self.rows.get(y).map_or(0, Row::len).saturating_sub(x) + 1;
+            y += 1;
+            x = 0;
+        }
+        count += end.x.saturating_sub(x);
+
+        self.edited = true;
+        for _ in 0..count {
+            self.delete(start);
         }
     }
 
     // Insert a given char into a given position in a document.
     pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.rows.len() {
+        if at.y > self.rows.len() || !self.is_editable_text() {
             return;
         }
 
@@ -108,13 +846,116 @@ impl Document {
         if at.y == self.len() {
             let mut row = Row::default();
             row.insert(0, c);
-            row.highlight(&self.file_type.highlight_options(), None);
             self.rows.push(row);
         } else if at.y < self.len() {
             let row = self.rows.get_mut(at.y).unwrap();
             row.insert(at.x, c);
-            row.highlight(&self.file_type.highlight_options(), None);
         }
+        self.rows_changed(at.y);
+    }
+
+    // Comment out the rows from first to last with the line comment token of the filetype, or
+    // remove the comments if every row is already commented. Empty rows are left alone, and the
+    // token goes after the smallest indentation so that the rows stay lined up.
+    pub fn toggle_comment(&mut self, first: usize, last: usize) -> Result<(), Error> {
+        if !self.is_editable_text() {
+            return Err(Error::other("the document can't be edited"));
+        }
+        let tokens = self.file_type.highlight_options().line_comments();
+        let token = tokens
+            .first()
+            .ok_or_else(|| Error::other("the filetype has no line comments"))?;
+        let last = cmp::min(last, self.rows.len().saturating_sub(1));
+        let rows = match self.rows.get_mut(first..=last) {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+
+        let texts: Vec<String> = rows
+            .iter()
+            .map(|row| String::from_utf8_lossy(row.as_bytes()).into_owned())
+            .collect();
+        let code: Vec<&str> = texts
+            .iter()
+            .map(String::as_str)
+            .filter(|text| !text.trim().is_empty())
+            .collect();
+        let commented = !code.is_empty()
+            && code.iter().all(|text| {
+                let text = text.trim_start();
+                tokens.iter().any(|token| text.starts_with(token.as_str()))
+            });
+        // The token goes after the whitespace every row starts with. It is compared a character
+        // at a time, so that tabs, spaces and wide spaces aren't taken for each other.
+        let indent = code
+            .iter()
+            .map(|text| &text[..text.len() - text.trim_start().len()])
+            .reduce(common_prefix)
+            .unwrap_or("");
+
+        for (row, text) in rows.iter_mut().zip(&texts) {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let changed = if commented {
+                let start = text.len() - text.trim_start().len();
+                let rest = &text[start..];
+                // The longest token that matches is removed, so that "--" isn't taken for "-".
+                let length = tokens
+                    .iter()
+                    .filter(|token| rest.starts_with(token.as_str()))
+                    .map(String::len)
+                    .max()
+                    .unwrap_or(0);
+                let rest = &rest[length..];
+                format!(
+                    "{}{}",
+                    &text[..start],
+                    rest.strip_prefix(' ').unwrap_or(rest)
+                )
+            } else {
+                format!("{}{} {}", indent, token, &text[indent.len()..])
+            };
+            *row = Row::from(&changed[..]);
+        }
+
+        self.rows_changed(first);
+        self.edited = true;
+        Ok(())
+    }
+
+    // Insert a whole string at a given position as a single operation, every affected row is
+    // highlighted only once. Returns the position right after the inserted text.
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.len() || text.is_empty() || !self.is_editable_text() {
+            return at.clone();
+        }
+
+        self.edited = true;
+        if at.y == self.len() {
+            self.rows.push(Row::default());
+        }
+
+        // The rest of the row is moved after the pasted text.
+        let tail = self.rows[at.y].split(at.x);
+        let mut lines = text.split('\n');
+        let mut y = at.y;
+        if let Some(first_line) = lines.next() {
+            self.rows[y].append(&Row::from(first_line));
+        }
+        for line in lines {
+            y += 1;
+            self.rows.insert(y, Row::from(line));
+        }
+
+        let end = Position {
+            x: self.rows[y].len(),
+            y,
+        };
+        self.rows[y].append(&tail);
+        self.rows_changed(at.y);
+
+        end
     }
 
     // Insert newline adds a new line, if the function is used from inside a row the row is
@@ -128,22 +969,38 @@ impl Document {
             return;
         }
 
-        let current_row = &mut self.rows[at.y];
-        let mut new_row = current_row.split(at.x);
-        current_row.highlight(&self.file_type.highlight_options(), None);
-        new_row.highlight(&self.file_type.highlight_options(), None);
+        let new_row = self.rows[at.y].split(at.x);
         self.rows.insert(at.y + 1, new_row);
+        self.rows_changed(at.y);
     }
 
     // Save saves all of the changes made to a document into a file.
+    // The file is never truncated in place, so a failed save leaves the old content intact.
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-                row.highlight(&self.file_type.highlight_options(), None);
+            match (&self.bytes, &self.large) {
+                (Some(bytes), _) => {
+                    write_atomically(Path::new(file_name), |file| file.write_all(bytes))?
+                }
+                (None, Some(large)) => {
+                    write_atomically(Path::new(file_name), |file| large.copy_to(file))?
+                }
+                (None, None) => {
+                    let content = encoding::encode(&self.contents(), self.encoding, self.bom)?;
+                    write_atomically(Path::new(file_name), |file| file.write_all(&content))?
+                }
+            }
+            self.disk_state = DiskState::read(file_name);
+            self.mixed_line_endings = false;
+            if !self.is_text() {
+                self.edited = false;
+                return Ok(());
+            }
+
+            // The file may have been saved under a new name, or have a modeline now.
+            if !self.file_type_set {
+                self.file_type = FileType::from(file_name, &self.contents());
+                self.reset_highlighting();
             }
 
             self.edited = false;
@@ -154,7 +1011,19 @@ impl Document {
 
     // Find returns a position of an query in a document. The direction dictates if we move up or
     // down in the searches.
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+    pub fn find(
+        &mut self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<Position> {
+        if self.is_hex() {
+            return self.find_bytes(query, at, direction);
+        }
+        if self.is_large() {
+            return self.find_large(query, at, direction);
+        }
+
         let mut position = Position { x: at.y, y: at.y };
         let start = if direction == SearchDirection::Forward {
             at.y
@@ -170,7 +1039,7 @@ impl Document {
 
         for _ in start..end {
             if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
+                if let Some(x) = row.find(query, position.x, direction) {
                     position.x = x;
                     return Some(position);
                 }
@@ -188,13 +1057,120 @@ impl Document {
         None
     }
 
-    pub fn highlight(&mut self, word: Option<&str>) {
-        for row in &mut self.rows {
-            row.highlight(&self.file_type.highlight_options(), word);
+    // Find a query in a large file. The lines are searched as bytes, and only the line with the
+    // match is loaded to find the exact position.
+    fn find_large(
+        &mut self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<Position> {
+        let file = self.large.as_mut()?;
+        let row = Row::from(&file.line(at.y)?[..]);
+        if let Some(x) = row.find(query, at.x, direction) {
+            return Some(Position { x, y: at.y });
         }
+
+        let y = if direction == SearchDirection::Forward {
+            file.find_after(query, at.y)?
+        } else {
+            file.find_before(query, at.y)?
+        };
+        let row = Row::from(&file.line(y)?[..]);
+        let start = if direction == SearchDirection::Forward {
+            0
+        } else {
+            row.len()
+        };
+        let x = row.find(query, start, direction)?;
+        Some(Position { x, y })
+    }
+
+    // Set the word whose matches are highlighted in the rows that are shown.
+    pub fn highlight(&mut self, word: Option<&str>) {
+        self.word = word.map(str::to_string);
     }
 
     pub fn is_edited(&self) -> bool {
         self.edited
     }
 }
+
+// Return the longest start the two texts share, which always ends at a character boundary.
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let length = a
+        .chars()
+        .zip(b.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    &a[..length]
+}
+
+// Return true if the current user may write to the file. A file that doesn't exist yet counts as
+// writable, the error comes when saving it if it can't be created.
+fn is_writable(file_name: &str) -> bool {
+    match CString::new(file_name) {
+        Ok(path) if Path::new(file_name).exists() => unsafe {
+            libc::access(path.as_ptr(), libc::W_OK) == 0
+        },
+        _ => true,
+    }
+}
+
+// Write the content into a temporary file next to the target and rename it over the target once
+// everything has been written to the disk. The content is written by the given function, so
+// that it can be copied from another file a part at a time. Symbolic links are followed, so the
+// link itself stays in place, and the permissions and the owner of an existing file are kept.
+fn write_atomically<W>(path: &Path, write: W) -> Result<(), Error>
+where
+    W: FnOnce(&mut fs::File) -> Result<(), Error>,
+{
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let directory = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temporary = directory.join(format!(".{}.see-{}.tmp", file_name, process::id()));
+
+    let create = || {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temporary)
+    };
+    let result = (|| {
+        let mut file = match create() {
+            // Only an editor with the same process id uses the name, so the file was left behind
+            // by one that crashed.
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                fs::remove_file(&temporary)?;
+                create()?
+            }
+            file => file?,
+        };
+        write(&mut file)?;
+        if let Ok(metadata) = fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+            // Only root can give files away, so failing to keep the owner is not an error.
+            let _ = unix_fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+        return result;
+    }
+
+    // Make sure the rename itself reaches the disk.
+    if let Ok(directory) = fs::File::open(&directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
@@ -1,15 +1,39 @@
+use crate::args::{Options, VERSION};
+use crate::color_depth::{self, ColorDepth};
+use crate::encoding;
+use crate::filetype;
+use crate::hex;
+use crate::large;
+use crate::swap;
+use crate::textmate;
 use crate::Document;
+use crate::Event;
+use crate::EventLoop;
+use crate::JobOutput;
+use crate::LineEnding;
 use crate::Row;
+use crate::Signal;
 use crate::Terminal;
-use std::env;
-use std::io::stdout;
+use crate::Theme;
+use crate::Timer;
+use std::cmp;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::time::Duration;
 use std::time::Instant;
-use termion::color;
 use termion::event::Key;
-use termion::raw::IntoRawMode;
+use termion::event::MouseButton;
+use termion::event::MouseEvent;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+const SCROLL_LINES: usize = 3;
+const SWAP_INTERVAL: Duration = Duration::from_secs(10);
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const HIGHLIGHT_DELAY: Duration = Duration::from_millis(10);
+const BINARY_MESSAGE: &str = "binary file shown in hex, ':set encoding=<name>' opens it as text";
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -17,7 +41,7 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -41,6 +65,7 @@ impl StatusMessage {
 enum EditorMode {
     Insert,
     View,
+    Pager,
 }
 
 #[derive(PartialEq)]
@@ -49,6 +74,15 @@ enum FileMoveDirection {
     Right, // Move 0->1
 }
 
+// A part of the screen showing a document. The screen is split into windows on top of each other,
+// and the focused window keeps its document and positions in the editor's own fields.
+#[derive(Clone)]
+struct Window {
+    document_index: usize,     // The document shown in the window
+    cursor_position: Position, // The cursor of the window, in the document's coordinates
+    offset: Position,          // How much the window is scrolled
+}
+
 pub struct Editor {
     quit: bool,                    // A quit signal
     terminal: Terminal,            // Different terminal controls
@@ -59,25 +93,616 @@ pub struct Editor {
     documents: Vec<Document>,      // A list of all the open documents
     document_index: usize,         // A field to keep track of the open document
     previous_key: termion::event::Key,
+    events: EventLoop, // The source of keypresses, timers, signals and job output
+    selection: Option<Position>, // The other end of the selection, the cursor being one end
+    checking_files: bool, // Set while asking about files changed by other programs
+    last_query: Option<String>, // The last search, repeated with 'n' and 'N' in the pager
+    read_only: bool,   // Whether documents are opened read-only, set with '-R' and in the pager
+    commands: Vec<String>, // The commands given with '-c', run once the editor starts
+    theme: Theme,      // The colors everything is drawn in, set with ':colorscheme'
+    windows: Vec<Window>, // The windows the screen is split into, from top to bottom
+    window_index: usize, // The focused window, whose entry in windows is out of date
+    terminated: bool,  // Set by SIGTERM or SIGHUP, which keep the swap files of unsaved changes
 }
 
 impl Editor {
-    pub fn run(&mut self) {
-        let _stdout = stdout().into_raw_mode().unwrap();
+    pub fn run(&mut self) -> Result<(), std::io::Error> {
+        // Offer to recover the documents that have a newer swap file.
+        for index in 0..self.documents.len() {
+            self.watch_document(index);
+            self.offer_recovery(index);
+        }
+        for command in mem::take(&mut self.commands) {
+            self.run_command(&command);
+        }
+        self.events.schedule(Timer::SwapFiles, SWAP_INTERVAL);
 
         loop {
-            if let Err(error) = self.refresh_editor() {
-                end(error);
-            }
+            self.refresh_editor()?;
 
             if self.quit {
-                break;
+                // The swap files are only needed if the editor didn't exit cleanly. When it was
+                // terminated, the unsaved changes are kept in them.
+                for document in &self.documents {
+                    if !(self.terminated && document.is_edited()) {
+                        swap::remove(document);
+                    }
+                }
+                return Ok(());
+            }
+
+            // Wake up once the status message has expired, so that it gets cleared from the
+            // screen even if the user doesn't press anything.
+            let shown_for = self.status_message.time.elapsed();
+            if shown_for < MESSAGE_TIMEOUT {
+                self.events
+                    .schedule(Timer::StatusMessage, MESSAGE_TIMEOUT - shown_for);
+            }
+
+            let event = self.events.wait()?;
+            self.process_event(event)?;
+        }
+    }
+
+    // Handle a single event from the event loop.
+    fn process_event(&mut self, event: Event) -> Result<(), std::io::Error> {
+        match event {
+            Event::Key(key) => self.process_press(key),
+            Event::Mouse(mouse) => {
+                self.process_mouse(mouse);
+                Ok(())
+            }
+            Event::Paste(text) => {
+                self.process_paste(&text);
+                Ok(())
+            }
+            // The message bar is redrawn after every event, so the expired message disappears.
+            Event::Timer(Timer::StatusMessage) => Ok(()),
+            // Drawing the screen highlights the next rows.
+            Event::Timer(Timer::Highlight) => Ok(()),
+            Event::Timer(Timer::SwapFiles) => {
+                self.write_swap_files();
+                self.events.schedule(Timer::SwapFiles, SWAP_INTERVAL);
+                Ok(())
+            }
+            Event::Signal(Signal::Resize) => {
+                self.terminal.update_size()?;
+                self.scroll();
+                Ok(())
+            }
+            Event::Signal(Signal::Suspend) => {
+                self.terminal.suspend()?;
+                self.scroll();
+                Ok(())
+            }
+            Event::Signal(Signal::Continue) => {
+                self.terminal.resume()?;
+                self.scroll();
+                Ok(())
+            }
+            // Closing the terminal doesn't ask about unsaved changes, so they go into swap files.
+            Event::Signal(Signal::Terminate) => {
+                self.write_swap_files();
+                self.terminated = true;
+                self.quit = true;
+                Ok(())
+            }
+            Event::Job(job) => {
+                self.handle_job_output(job);
+                Ok(())
+            }
+            Event::Stdin(text) => {
+                if let Some(document) = self.documents.iter_mut().find(|d| d.is_stdin()) {
+                    document.append_text(&text);
+                }
+                Ok(())
+            }
+            Event::FileChanged => {
+                self.check_files();
+                Ok(())
+            }
+            Event::Timer(Timer::FileCheck) => {
+                self.check_files();
+                self.events.schedule(Timer::FileCheck, FILE_CHECK_INTERVAL);
+                Ok(())
+            }
+        }
+    }
+
+    // Start watching the file of a document for changes made by other programs. If the file can't
+    // be watched, all files are checked periodically instead.
+    fn watch_document(&mut self, index: usize) {
+        let file_name = match &self.documents[index].file_name {
+            Some(file_name) if !file_name.is_empty() => file_name,
+            _ => return,
+        };
+        if !self.events.watch(Path::new(file_name)) {
+            self.events.schedule(Timer::FileCheck, FILE_CHECK_INTERVAL);
+        }
+    }
+
+    // Look for documents whose files have been changed by other programs. Documents without
+    // changes are reloaded right away, for the others the user decides what to do.
+    fn check_files(&mut self) {
+        // Asking the user handles events too, which must not start asking again.
+        if self.checking_files {
+            return;
+        }
+        self.checking_files = true;
+
+        for index in 0..self.documents.len() {
+            if !self.documents[index].changed_on_disk() {
+                continue;
             }
+            if self.documents[index].is_following() {
+                self.follow_document(index);
+            } else if self.documents[index].is_edited() {
+                self.resolve_disk_changes(index);
+            } else {
+                self.reload_document(index);
+            }
+        }
 
-            if let Err(error) = self.process_press() {
-                end(error);
+        self.checking_files = false;
+    }
+
+    // Read the lines added to the file of a followed document. The view follows the new lines as
+    // long as the cursor is on the last line, moving the cursor or scrolling up pauses that.
+    fn follow_document(&mut self, index: usize) {
+        let at_bottom = index == self.document_index && self.follows_end();
+        match self.documents[index].read_appended() {
+            Ok(true) => (),
+            // Reloading would throw away the changes made in the editor, so the user decides.
+            Ok(false) if self.documents[index].is_edited() => {
+                self.resolve_disk_changes(index);
+                return;
+            }
+            Ok(false) => self.reload_document(index),
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("could not follow: {}", error));
+                return;
             }
         }
+
+        if at_bottom {
+            self.move_to_last_line();
+        }
+    }
+
+    // Return true if the cursor is on the last line of the current document and shown on the
+    // screen.
+    fn follows_end(&self) -> bool {
+        let height = self.window_height();
+        let y = self.cursor_position.y;
+        y.saturating_add(1) >= self.documents[self.document_index].len()
+            && y >= self.offset.y
+            && y < self.offset.y.saturating_add(height)
+    }
+
+    // Move the cursor to the start of the last line of the current document.
+    fn move_to_last_line(&mut self) {
+        let len = self.documents[self.document_index].count_lines();
+        self.cursor_position = Position {
+            x: 0,
+            y: len.saturating_sub(1),
+        };
+        self.scroll();
+    }
+
+    // Start or stop following the lines added to the current document, like 'tail -f'.
+    fn toggle_following(&mut self) {
+        let document = &mut self.documents[self.document_index];
+        let following = !document.is_following();
+        if let Err(error) = document.set_following(following) {
+            self.status_message = StatusMessage::from(error.to_string());
+            return;
+        }
+
+        if following {
+            // Catch up with what was added before following started.
+            if document.changed_on_disk() {
+                self.follow_document(self.document_index);
+            }
+            self.move_to_last_line();
+        }
+    }
+
+    // Ask the user whether to reload an edited document whose file has changed on the disk, keep
+    // the edited version, or look at the differences first.
+    fn resolve_disk_changes(&mut self, index: usize) {
+        let file_name = self.documents[index].file_name.clone().unwrap_or_default();
+        let question = format!(
+            "{} changed on disk: (r)eload, (k)eep your version or show (d)iff: ",
+            file_name
+        );
+        loop {
+            let action = self.prompt(&question, |_, _, _| {}).unwrap_or(None);
+            match action.as_deref() {
+                Some("r") | Some("reload") => self.reload_document(index),
+                Some("d") | Some("diff") => {
+                    self.show_disk_diff(index);
+                    continue;
+                }
+                // Keeping the version in the editor means that saving it will overwrite the file.
+                _ => self.documents[index].ignore_disk_changes(),
+            }
+            return;
+        }
+    }
+
+    // Read a document again from its file.
+    fn reload_document(&mut self, index: usize) {
+        let document = &mut self.documents[index];
+        let file_name = document.file_name.clone().unwrap_or_default();
+        if let Err(error) = document.reload() {
+            self.status_message =
+                StatusMessage::from(format!("could not reload {}: {}", file_name, error));
+            return;
+        }
+        swap::remove(document);
+        self.status_message = StatusMessage::from(format!("reloaded {}", file_name));
+
+        if index == self.document_index {
+            let len = self.documents[index].len();
+            self.cursor_position.y = cmp::min(self.cursor_position.y, len);
+            self.selection = None;
+            self.move_cursor(Key::Null);
+            self.scroll();
+        }
+    }
+
+    // Open the differences between the file on the disk and the document as a new document.
+    fn show_disk_diff(&mut self, index: usize) {
+        let document = &self.documents[index];
+        let file_name = document.file_name.clone().unwrap_or_default();
+        let diff = Command::new("diff")
+            .arg("-u")
+            .arg("--label")
+            .arg(format!("{} (on disk)", file_name))
+            .arg("--label")
+            .arg(format!("{} (edited)", file_name))
+            .arg("--")
+            .arg(&file_name)
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take() {
+                    document.write_to(&mut stdin)?;
+                }
+                child.wait_with_output()
+            });
+
+        match diff {
+            Ok(output) => {
+                let text = String::from_utf8_lossy(&output.stdout);
+                let mut document = Document::from_text(&text);
+                document.set_read_only(true);
+                self.documents.push(document);
+                self.document_index = self.documents.len() - 1;
+                self.cursor_position = Position::default();
+                self.scroll();
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("could not run diff: {}", error));
+            }
+        }
+    }
+
+    // Write a swap file for every document that has unsaved changes.
+    pub fn write_swap_files(&mut self) {
+        let mut failed = false;
+        for document in &self.documents {
+            if document.is_edited() && swap::write(document).is_err() {
+                failed = true;
+            }
+        }
+
+        if failed {
+            self.status_message = StatusMessage::from("error writing swap file".to_string());
+        }
+    }
+
+    // If the document at the given index has a swap file that is newer than the file, ask the
+    // user whether to recover the content of the swap file.
+    fn offer_recovery(&mut self, index: usize) {
+        let file_name = match &self.documents[index].file_name {
+            Some(file_name) => file_name.clone(),
+            None => return,
+        };
+        let swapped = match swap::recoverable(&file_name) {
+            Some(swapped) => swapped,
+            None => return,
+        };
+
+        let (added, removed) = swap::line_changes(
+            &String::from_utf8_lossy(&self.documents[index].to_bytes()),
+            &String::from_utf8_lossy(&swapped),
+        );
+        let question = format!(
+            "newer swap file for {} (+{} -{} lines): (r)ecover, (d)iscard or (o)pen read-only: ",
+            file_name, added, removed
+        );
+        let action = self.prompt(&question, |_, _, _| {}).unwrap_or(None);
+        match action.as_deref() {
+            Some("r") | Some("recover") => {
+                self.documents[index].replace_contents(&swapped);
+                self.status_message = StatusMessage::from("recovered from swap file".to_string());
+            }
+            Some("d") | Some("discard") => swap::remove(&self.documents[index]),
+            // The swap file is left in place, so that it can still be recovered later. The file is
+            // read-only so that saving it doesn't get in the way of recovering.
+            Some("o") | Some("open") => self.documents[index].set_read_only(true),
+            _ => (),
+        }
+    }
+
+    // Handle the mouse. Clicking moves the cursor, dragging selects text and the wheel scrolls the
+    // view under the mouse without moving the cursor. Clicking another window focuses it, and
+    // clicking a file name in the status bar opens that document.
+    fn process_mouse(&mut self, mouse: MouseEvent) {
+        let height = self.terminal.size().height as usize;
+        match mouse {
+            MouseEvent::Press(MouseButton::WheelUp, _, y) => {
+                self.scroll_window_at(y.saturating_sub(1) as usize, -(SCROLL_LINES as isize));
+            }
+            MouseEvent::Press(MouseButton::WheelDown, _, y) => {
+                self.scroll_window_at(y.saturating_sub(1) as usize, SCROLL_LINES as isize);
+            }
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                // The coordinates given by the terminal start from one.
+                let x = x.saturating_sub(1) as usize;
+                let y = y.saturating_sub(1) as usize;
+                if y < height {
+                    // The line below a window belongs to it, clicking there only focuses it.
+                    let areas = self.window_areas();
+                    let index = match areas.iter().position(|area| y <= area.end) {
+                        Some(index) => index,
+                        None => return,
+                    };
+                    self.focus_window(index);
+                    if areas[index].contains(&y) {
+                        self.move_cursor_to_screen(x, y - areas[index].start);
+                        self.selection = Some(self.cursor_position.clone());
+                    }
+                } else if y == height {
+                    self.select_document_at(x);
+                }
+            }
+            // Dragging past the edges of the window selects up to its first or last row.
+            MouseEvent::Hold(x, y) if self.selection.is_some() => {
+                let area = self.window_areas()[self.window_index].clone();
+                let x = x.saturating_sub(1) as usize;
+                let y = (y.saturating_sub(1) as usize).clamp(area.start, area.end);
+                let y = cmp::min(y - area.start, area.len().saturating_sub(1));
+                self.move_cursor_to_screen(x, y);
+            }
+            // A click without dragging doesn't select anything.
+            MouseEvent::Release(_, _) if self.selection.as_ref() == Some(&self.cursor_position) => {
+                self.selection = None;
+            }
+            _ => (),
+        }
+    }
+
+    // Insert pasted text in one go instead of handling it one keypress at a time. Pasting only
+    // works in the insert mode.
+    fn process_paste(&mut self, text: &str) {
+        if self.editor_mode != EditorMode::Insert || self.warn_if_read_only() {
+            return;
+        }
+
+        if let Some((start, end)) = self.selection_range() {
+            self.documents[self.document_index].delete_range(&start, &end);
+            self.cursor_position = start;
+        }
+        self.selection = None;
+
+        self.cursor_position =
+            self.documents[self.document_index].insert_str(&self.cursor_position, text);
+        self.scroll();
+    }
+
+    // Move the cursor to the text shown at the given screen coordinates.
+    fn move_cursor_to_screen(&mut self, x: usize, y: usize) {
+        let document = &self.documents[self.document_index];
+        let y = cmp::min(self.offset.y.saturating_add(y), document.len());
+        let width = document.row(y).map_or(0, Row::len);
+        let x = cmp::min(self.offset.x.saturating_add(x), width);
+        self.cursor_position = Position { x, y };
+        if document.is_hex() {
+            self.move_hex_cursor(Key::Null);
+        }
+    }
+
+    // Switch to the document whose name is shown at the given column of the status bar.
+    fn select_document_at(&mut self, x: usize) {
+        let (_, document_names) = self.status_bar_text();
+        if let Some((_, index)) = document_names.iter().find(|(range, _)| range.contains(&x)) {
+            self.document_index = *index;
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+        }
+    }
+
+    // Return the screen rows of each window. The windows share the height of the screen evenly,
+    // and every window but the last has a line with the name of its document below it. The last
+    // window has the status bar for that.
+    fn window_areas(&self) -> Vec<Range<usize>> {
+        let height = self.terminal.size().height as usize;
+        let count = self.windows.len();
+        let each = height.saturating_sub(count - 1) / count;
+        let mut top = 0;
+        let mut areas = Vec::with_capacity(count);
+        for index in 0..count {
+            let rows = if index + 1 == count {
+                height.saturating_sub(top)
+            } else {
+                each
+            };
+            areas.push(top..top + rows);
+            top += rows + 1;
+        }
+        areas
+    }
+
+    // Return the number of rows in the focused window.
+    fn window_height(&self) -> usize {
+        cmp::max(self.window_areas()[self.window_index].len(), 1)
+    }
+
+    // Return the window at an index, with the current positions of the focused one.
+    fn window(&self, index: usize) -> Window {
+        if index == self.window_index {
+            Window {
+                document_index: self.document_index,
+                cursor_position: self.cursor_position.clone(),
+                offset: self.offset.clone(),
+            }
+        } else {
+            self.windows[index].clone()
+        }
+    }
+
+    // Show a window's document and positions in the focused window.
+    fn load_window(&mut self, window: Window) {
+        self.document_index = window.document_index;
+        self.offset = window.offset;
+        self.selection = None;
+        // The document may have become shorter while the window wasn't focused.
+        let len = self.documents[self.document_index].len();
+        self.cursor_position = window.cursor_position;
+        self.cursor_position.y = cmp::min(self.cursor_position.y, len);
+        self.move_cursor(Key::Null);
+        self.scroll();
+    }
+
+    // Move the focus to another window, keeping the positions of the one that loses it.
+    fn focus_window(&mut self, index: usize) {
+        if index == self.window_index || index >= self.windows.len() {
+            return;
+        }
+        self.windows[self.window_index] = self.window(self.window_index);
+        self.window_index = index;
+        self.load_window(self.windows[index].clone());
+    }
+
+    // Split the focused window in two. The new window goes below it and gets the focus, showing
+    // the given file or else the same document.
+    fn split_window(&mut self, file_name: Option<&str>) {
+        // Every window needs at least one row and the line below it.
+        let height = self.terminal.size().height as usize;
+        if 2 * self.windows.len() + 1 > height {
+            self.status_message =
+                StatusMessage::from("not enough room for another window".to_string());
+            return;
+        }
+
+        let mut window = self.window(self.window_index);
+        if let Some(file_name) = file_name {
+            let open = self
+                .documents
+                .iter()
+                .position(|document| document.file_name.as_deref() == Some(file_name));
+            let document_index = match open.or_else(|| self.open_document(file_name)) {
+                Some(document_index) => document_index,
+                None => return,
+            };
+            if document_index != window.document_index {
+                window = Window {
+                    document_index,
+                    cursor_position: Position::default(),
+                    offset: Position::default(),
+                };
+            }
+        }
+
+        self.windows.insert(self.window_index + 1, window);
+        self.focus_window(self.window_index + 1);
+    }
+
+    // Close the focused window, the document stays open. The window above gets the focus, or the
+    // one below if the closed window was the first.
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.status_message = StatusMessage::from("can't close the last window".to_string());
+            return;
+        }
+
+        let closed = self.window_index;
+        self.focus_window(if closed == 0 { 1 } else { closed - 1 });
+        self.windows.remove(closed);
+        if closed < self.window_index {
+            self.window_index -= 1;
+        }
+    }
+
+    // Scroll the window at a row of the screen by a number of lines, without moving its cursor.
+    fn scroll_window_at(&mut self, y: usize, lines: isize) {
+        let index = match self.window_areas().iter().position(|area| y <= area.end) {
+            Some(index) => index,
+            None => return,
+        };
+        let mut window = self.window(index);
+        let last_row = self.documents[window.document_index]
+            .len()
+            .saturating_sub(1);
+        window.offset.y = cmp::min(window.offset.y.saturating_add_signed(lines), last_row);
+        if index == self.window_index {
+            self.offset = window.offset;
+        } else {
+            self.windows[index] = window;
+        }
+    }
+
+    // Keep the windows pointing to the right documents after a document has been closed. The
+    // windows that showed it show the document before it instead.
+    fn document_removed(&mut self, removed: usize) {
+        self.windows[self.window_index] = self.window(self.window_index);
+        for window in &mut self.windows {
+            if window.document_index > removed {
+                window.document_index -= 1;
+            } else if window.document_index == removed {
+                *window = Window {
+                    document_index: removed.saturating_sub(1),
+                    cursor_position: Position::default(),
+                    offset: Position::default(),
+                };
+            }
+        }
+        self.load_window(self.windows[self.window_index].clone());
+    }
+
+    // Return the selection ordered so that the first position comes before the second.
+    fn selection_range(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection.clone()?;
+        let cursor = self.cursor_position.clone();
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    // Display the result of a finished background job in the message bar.
+    fn handle_job_output(&mut self, job: JobOutput) {
+        let message = match job.output {
+            Ok(output) => {
+                // Show the last line the command printed, since that usually tells the most.
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let last_line = stdout
+                    .lines()
+                    .chain(stderr.lines())
+                    .rfind(|line| !line.trim().is_empty())
+                    .unwrap_or("")
+                    .to_string();
+                format!("!{} ({}) {}", job.command, output.status, last_line)
+            }
+            Err(error) => format!("!{} failed: {}", job.command, error),
+        };
+        self.status_message = StatusMessage::from(message);
     }
 
     // Change the editor move to which ever mode in the EditorMode enum.
@@ -85,9 +710,28 @@ impl Editor {
         self.editor_mode = to_change;
     }
 
+    // Warn about trying to change a read-only document. Returns true if the current document is
+    // read-only.
+    fn warn_if_read_only(&mut self) -> bool {
+        if !self.documents[self.document_index].is_read_only() {
+            return false;
+        }
+        self.status_message =
+            StatusMessage::from("the document is read-only, ':w!' writes it anyway".to_string());
+        true
+    }
+
     // Save the current document. If a the user is editing a unnamed document, this function will
     // prompt them to name that file to save it.
     fn handle_file_save(&mut self) {
+        if self.warn_if_read_only() {
+            return;
+        }
+        self.write_file();
+    }
+
+    // Write the current document even if it is read-only.
+    fn write_file(&mut self) {
         if self.documents[self.document_index].file_name.is_none() {
             let new_name = self.prompt("save as: ", |_, _, _| {}).unwrap_or(None);
             if new_name.is_none() {
@@ -97,11 +741,44 @@ impl Editor {
             self.documents[self.document_index].file_name = new_name;
         }
 
+        // Don't overwrite changes made by other programs without asking.
+        if self.documents[self.document_index].changed_on_disk() {
+            let action = self
+                .prompt(
+                    "file changed on disk since it was read, overwrite? (y/n) ",
+                    |_, _, _| {},
+                )
+                .unwrap_or(None);
+            if !matches!(action.as_deref(), Some("y") | Some("yes")) {
+                self.status_message = StatusMessage::from("save stopped".to_string());
+                return;
+            }
+        }
+
+        // The rows don't keep their own line breaks, so saving a file with mixed line endings
+        // changes every line to the same one.
+        let document = &self.documents[self.document_index];
+        if document.has_mixed_line_endings() {
+            let question = format!(
+                "file has mixed line endings, save all lines as {}? (y/n) ",
+                document.line_ending().name()
+            );
+            let action = self.prompt(&question, |_, _, _| {}).unwrap_or(None);
+            if !matches!(action.as_deref(), Some("y") | Some("yes")) {
+                self.status_message = StatusMessage::from("save stopped".to_string());
+                return;
+            }
+        }
+
         // Prompt the user with a message describing the execution of the operation.
-        if self.documents[self.document_index].save().is_ok() {
-            self.status_message = StatusMessage::from("file saved".to_string());
-        } else {
-            self.status_message = StatusMessage::from("error writing file".to_string());
+        match self.documents[self.document_index].save() {
+            Ok(()) => {
+                swap::remove(&self.documents[self.document_index]);
+                self.status_message = StatusMessage::from("file saved".to_string());
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("error writing file: {}", error));
+            }
         }
     }
 
@@ -118,14 +795,10 @@ impl Editor {
             .prompt("exit without saving? (y/n)", |_, _, _| {})
             .unwrap_or(None);
 
-        match action {
-            Some(action) => {
-                if action.to_string() == "yes" || action.to_string() == "y" {
-                    self.quit = true;
-                    return;
-                }
+        if let Some(action) = action {
+            if action == "yes" || action == "y" {
+                self.quit = true;
             }
-            None => return,
         }
     }
 
@@ -142,7 +815,7 @@ impl Editor {
 
         match action {
             Some(action) => {
-                if action.to_string() == "yes" || action.to_string() == "y" {
+                if action == "yes" || action == "y" {
                     return true;
                 }
             }
@@ -161,15 +834,10 @@ impl Editor {
             return;
         }
 
-        if self.document_index == 0 {
-            if self.exit_document_without_save(self.document_index) {
-                self.documents.remove(self.document_index);
-            }
-        } else {
-            if self.exit_document_without_save(self.document_index) {
-                self.document_index -= 1;
-                self.documents.remove(self.document_index);
-            }
+        if self.exit_document_without_save(self.document_index) {
+            let index = self.document_index;
+            swap::remove(&self.documents.remove(index));
+            self.document_removed(index);
         }
     }
 
@@ -178,40 +846,205 @@ impl Editor {
     fn handle_command(&mut self) {
         let command = self.prompt(":", |_, _, _| {}).unwrap_or(None);
 
-        if command.is_some() {
-            // Match the command by the user to some other commands.
-            match command.unwrap().as_str() {
-                "s" => self.handle_file_save(),
-                "sq" => {
-                    // Save the file
-                    self.handle_file_save();
-
-                    // The function also does not request the user to give any information if the
-                    // file is already saved, this is why we first save the file.
-                    self.check_exit_without_saving();
-                }
-                "sae" => {
-                    self.save_all_documents();
-                    // since the comamnd ends with 'e' we will quit the editor
+        if let Some(command) = command {
+            self.run_command(&command);
+        }
+    }
+
+    // Run a single command, typed after ':' or given with '-c' on the command line.
+    fn run_command(&mut self, command: &str) {
+        // Commands starting with '!' are run as shell commands in the background.
+        if let Some(shell_command) = command.strip_prefix('!') {
+            self.events.spawn_job(shell_command);
+            self.status_message = StatusMessage::from(format!("running {}", shell_command));
+            return;
+        }
+
+        if let Some(option) = command.strip_prefix("set ") {
+            self.set_option(option.trim());
+            return;
+        }
+
+        if let Some(file_name) = command
+            .strip_prefix("split ")
+            .or_else(|| command.strip_prefix("sp "))
+        {
+            self.split_window(Some(file_name.trim()));
+            return;
+        }
+
+        if let Some(name) = command.strip_prefix("colorscheme ") {
+            match Theme::load(name.trim()) {
+                Ok(theme) => self.theme = theme,
+                Err(error) => self.status_message = StatusMessage::from(error.to_string()),
+            }
+            return;
+        }
+
+        // Match the command by the user to some other commands.
+        match command {
+            "s" | "w" => self.handle_file_save(),
+            "s!" | "w!" => self.write_file(),
+            "sq" => {
+                // Save the file
+                self.handle_file_save();
+
+                // The function also does not request the user to give any information if the
+                // file is already saved, this is why we first save the file.
+                self.check_exit_without_saving();
+            }
+            "sae" => {
+                // since the comamnd ends with 'e' we will quit the editor, unless something
+                // couldn't be saved
+                if self.save_all_documents() {
                     self.quit = true;
                 }
-                "sa" => {
-                    self.save_all_documents();
+            }
+            "sa" => {
+                self.save_all_documents();
+            }
+            "hex" => self.toggle_hex(),
+            "follow" => self.toggle_following(),
+            "comment" => self.toggle_comment(None),
+            "split" | "sp" => self.split_window(None),
+            "close" => self.close_window(),
+            "colorscheme" => {
+                self.status_message = StatusMessage::from(self.theme.name().to_string());
+            }
+            _ => {
+                self.status_message = StatusMessage::from(format!("unknown command: {}", command));
+            }
+        }
+    }
+
+    // Comment out the rows of the selection, or the row of the cursor, or remove their comments.
+    fn toggle_comment(&mut self, selection: Option<(Position, Position)>) {
+        if self.warn_if_read_only() {
+            return;
+        }
+        let (first, last) = match selection {
+            Some((start, end)) => (start.y, end.y),
+            None => (self.cursor_position.y, self.cursor_position.y),
+        };
+        if let Err(error) = self.documents[self.document_index].toggle_comment(first, last) {
+            self.status_message = StatusMessage::from(error.to_string());
+        }
+    }
+
+    // Set an option of the current document, given in the form of 'name=value'.
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        let document = &mut self.documents[self.document_index];
+        match (name, value) {
+            ("fileformat", "unix") | ("ff", "unix") => document.set_line_ending(LineEnding::Unix),
+            ("fileformat", "dos") | ("ff", "dos") => document.set_line_ending(LineEnding::Dos),
+            ("fileencoding", label) | ("fenc", label) => match encoding::from_label(label) {
+                Some(encoding) => document.set_encoding(encoding),
+                None => {
+                    self.status_message =
+                        StatusMessage::from(format!("unknown encoding: {}", label));
+                }
+            },
+            ("encoding", label) | ("enc", label) => self.reopen_with_encoding(label),
+            ("filetype", name) | ("ft", name) => {
+                if let Err(error) = document.set_file_type(name) {
+                    self.status_message = StatusMessage::from(error.to_string());
                 }
-                _ => (),
+            }
+            ("colors", depth) => match ColorDepth::from_name(depth) {
+                Some(depth) => color_depth::set_depth(depth),
+                None => {
+                    self.status_message =
+                        StatusMessage::from(format!("unknown color depth: {}", depth));
+                }
+            },
+            ("readonly", "") | ("ro", "") => document.set_read_only(true),
+            ("noreadonly", "") | ("noro", "") => document.set_read_only(false),
+            // Applies to the files opened after it has been set.
+            ("largefile", megabytes) => match megabytes.parse() {
+                Ok(megabytes) => large::set_threshold(megabytes),
+                Err(_) => {
+                    self.status_message =
+                        StatusMessage::from(format!("invalid size: {}", megabytes));
+                }
+            },
+            _ => {
+                self.status_message = StatusMessage::from(format!("unknown option: {}", option));
             }
         }
     }
 
-    // Save all buffers saves each buffer without asking the user for any kind of input.
-    fn save_all_documents(&mut self) {
+    // Read the current document again from the disk using the given encoding.
+    fn reopen_with_encoding(&mut self, label: &str) {
+        let document = &self.documents[self.document_index];
+        let encoding = match encoding::from_label(label) {
+            Some(encoding) => encoding,
+            None => {
+                self.status_message = StatusMessage::from(format!("unknown encoding: {}", label));
+                return;
+            }
+        };
+        if document.is_edited() {
+            self.status_message =
+                StatusMessage::from("save the changes before changing the encoding".to_string());
+            return;
+        }
+
+        let file_name = document.file_name.clone().unwrap_or_default();
+        let read_only = document.is_read_only();
+        match Document::open_with_encoding(&file_name, Some(encoding)) {
+            // The new document is read-only if the old one was, and its file is watched again.
+            Ok(mut document) => {
+                if read_only {
+                    document.set_read_only(true);
+                }
+                self.documents[self.document_index] = document;
+                self.watch_document(self.document_index);
+                self.cursor_position = Position::default();
+                self.scroll();
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("could not open {}: {}", file_name, error));
+            }
+        }
+    }
+
+    // Save all buffers saves each buffer without asking the user for any kind of input. The
+    // documents that couldn't be saved are reported together, and false is returned if there were
+    // any.
+    fn save_all_documents(&mut self) -> bool {
+        let mut failures = Vec::new();
         for doc in &mut self.documents {
-            if doc.save().is_ok() {
-                self.status_message = StatusMessage::from("file saved".to_string());
-            } else {
-                self.status_message = StatusMessage::from("error writing file".to_string());
+            let name = doc.file_name.clone().unwrap_or_default();
+            if doc.is_read_only() {
+                if doc.is_edited() {
+                    failures.push(format!("{} is read-only", name));
+                }
+                continue;
             }
+            // Saving without asking must not overwrite changes made by other programs.
+            if doc.changed_on_disk() {
+                failures.push(format!("{} changed on disk", name));
+                continue;
+            }
+            if doc.is_edited() && doc.has_mixed_line_endings() {
+                failures.push(format!("{} has mixed line endings", name));
+                continue;
+            }
+
+            match doc.save() {
+                Ok(()) => swap::remove(doc),
+                Err(error) => failures.push(format!("{}: {}", name, error)),
+            }
+        }
+
+        if failures.is_empty() {
+            self.status_message = StatusMessage::from("files saved".to_string());
+            return true;
         }
+        self.status_message = StatusMessage::from(format!("not saved: {}", failures.join(", ")));
+        false
     }
 
     // Search for a word in the current document. The user can move in the order from left-to-right
@@ -238,7 +1071,7 @@ impl Editor {
                 }
                 // If a position is found move the cursor to that position.
                 if let Some(position) = editor.documents[editor.document_index].find(
-                    &query,
+                    query,
                     &editor.cursor_position,
                     direction,
                 ) {
@@ -257,17 +1090,94 @@ impl Editor {
             self.cursor_position = old_position;
             self.scroll();
         }
+        if query.is_some() {
+            self.last_query = query;
+        }
 
         self.documents[self.document_index].highlight(None);
     }
 
+    // Move to the next or the previous match of the last search.
+    fn search_again(&mut self, direction: SearchDirection) {
+        let query = match &self.last_query {
+            Some(query) => query.clone(),
+            None => return,
+        };
+        let mut at = self.cursor_position.clone();
+        if direction == SearchDirection::Forward {
+            at.x = at.x.saturating_add(1);
+        }
+
+        match self.documents[self.document_index].find(&query, &at, direction) {
+            Some(position) => {
+                self.cursor_position = position;
+                self.scroll();
+            }
+            None => {
+                self.status_message = StatusMessage::from(format!("pattern not found: {}", query));
+            }
+        }
+    }
+
+    // Scroll the view in the pager by a number of lines, the cursor stays on the first line of the
+    // view.
+    fn scroll_view(&mut self, lines: isize) {
+        let height = self.window_height();
+        let last = self.documents[self.document_index]
+            .len()
+            .saturating_sub(height);
+        let y = self.offset.y.saturating_add_signed(lines);
+        self.offset.y = cmp::min(y, last);
+        self.cursor_position = Position {
+            x: 0,
+            y: self.offset.y,
+        };
+    }
+
+    // Handle the keys of the pager, which are the same as in 'less'.
+    fn process_pager_press(&mut self, pressed_key: Key) {
+        let page = self.window_height() as isize;
+        match pressed_key {
+            Key::Char('q') | Key::Char('Q') => self.quit = true,
+            Key::Char(' ') | Key::Char('f') | Key::Ctrl('f') | Key::PageDown => {
+                self.scroll_view(page)
+            }
+            Key::Char('b') | Key::Ctrl('b') | Key::PageUp => self.scroll_view(-page),
+            Key::Char('d') | Key::Ctrl('d') => self.scroll_view(page / 2),
+            Key::Char('u') | Key::Ctrl('u') => self.scroll_view(-page / 2),
+            Key::Char('j') | Key::Char('e') | Key::Char('\n') | Key::Down => self.scroll_view(1),
+            Key::Char('k') | Key::Char('y') | Key::Up => self.scroll_view(-1),
+            Key::Char('g') | Key::Char('<') | Key::Home => self.scroll_view(isize::MIN),
+            Key::Char('G') | Key::Char('>') | Key::End => {
+                self.documents[self.document_index].count_lines();
+                self.scroll_view(isize::MAX);
+            }
+            Key::Char('/') => self.search(),
+            Key::Char('n') => self.search_again(SearchDirection::Forward),
+            Key::Char('N') => self.search_again(SearchDirection::Backward),
+            Key::Char(':') => self.handle_command(),
+            Key::Ctrl('z') => {
+                if let Err(error) = self.terminal.suspend() {
+                    self.status_message = StatusMessage::from(error.to_string());
+                }
+            }
+            Key::Left => self.move_in_documents(FileMoveDirection::Left),
+            Key::Right => self.move_in_documents(FileMoveDirection::Right),
+            _ => (),
+        }
+    }
+
     // Handle all the keypresses the user types as input.
-    fn process_press(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+    fn process_press(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        // Any keypress ends the selection made with the mouse, deleting uses it before that.
+        let selection = self.selection_range();
+        self.selection = None;
 
         // There are different keybindings depending on which mode you're in, so check which
         // keybindings to use.
-        if self.editor_mode == EditorMode::View {
+        if self.editor_mode == EditorMode::Pager {
+            self.process_pager_press(pressed_key);
+        } else if self.editor_mode == EditorMode::View {
             // EditorMode::View is similar to vim's normal mode
             match pressed_key {
                 Key::Char('i') => self.change_mode(EditorMode::Insert),
@@ -276,20 +1186,26 @@ impl Editor {
                 Key::Char('k') => self.move_cursor(Key::Up),
                 Key::Char('l') => self.move_cursor(Key::Right),
                 Key::Char(':') => self.handle_command(),
-                Key::Char('g') => {
-                    if self.previous_key == Key::Char('g') {
-                        self.move_cursor(Key::End);
-                    }
+                Key::Char('g') if self.previous_key == Key::Char('g') => {
+                    self.move_cursor(Key::End);
                 }
                 Key::Ctrl('q') => self.check_exit_without_saving(),
                 Key::Ctrl('s') => self.handle_file_save(),
-                Key::Ctrl('z') => self.close_current_file(),
+                Key::Ctrl('z') => {
+                    self.terminal.suspend()?;
+                    self.scroll();
+                }
+                Key::Ctrl('w') => self.close_current_file(),
                 Key::Ctrl('f') => self.search(),
                 Key::Ctrl('p') => self.open_new_file(),
                 Key::Ctrl('e') => self.move_cursor(Key::End),
                 Key::Ctrl('h') => self.move_cursor(Key::Home),
+                // Ctrl-/ is read as Ctrl-7.
+                Key::Ctrl('7') => self.toggle_comment(selection),
                 Key::Left => self.move_in_documents(FileMoveDirection::Left),
                 Key::Right => self.move_in_documents(FileMoveDirection::Right),
+                // Move the focus to the next window, going back to the first from the last.
+                Key::Char('\t') => self.focus_window((self.window_index + 1) % self.windows.len()),
                 _ => (),
             }
 
@@ -302,21 +1218,40 @@ impl Editor {
                 Key::Ctrl('s') => self.handle_file_save(),
                 Key::Ctrl('f') => self.search(),
                 Key::Ctrl('n') => self.open_new_file(),
+                Key::Ctrl('7') => self.toggle_comment(selection),
+                Key::Ctrl('z') => {
+                    self.terminal.suspend()?;
+                    self.scroll();
+                }
+                Key::Char(_) | Key::Delete | Key::Backspace if self.warn_if_read_only() => (),
+                // The hex view is edited by overwriting the nibble under the cursor.
+                Key::Char(c) if self.documents[self.document_index].is_hex() => {
+                    if let Some(value) = c.to_digit(16) {
+                        self.documents[self.document_index]
+                            .set_nibble(&self.cursor_position, value as u8);
+                        self.move_cursor(Key::Right);
+                    }
+                }
                 Key::Char(c) => {
                     // Insert the wanted character at the position of the cursor. Also move the
                     // cursor so it seems more interactive.
                     self.documents[self.document_index].insert(&self.cursor_position, c);
                     self.move_cursor(Key::Right);
                 }
-                Key::Delete => self.documents[self.document_index].delete(&self.cursor_position),
-                Key::Backspace => {
-                    // Check that we don't use negative indices.
-                    if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                        // Move the cursor back and remove the character at the cursor position.
-                        self.move_cursor(Key::Left);
-                        self.documents[self.document_index].delete(&self.cursor_position);
+                // Remove the whole selection if there is one.
+                Key::Delete | Key::Backspace if selection.is_some() => {
+                    if let Some((start, end)) = selection {
+                        self.documents[self.document_index].delete_range(&start, &end);
+                        self.cursor_position = start;
                     }
                 }
+                Key::Delete => self.documents[self.document_index].delete(&self.cursor_position),
+                // Check that we don't use negative indices.
+                Key::Backspace if self.cursor_position.x > 0 || self.cursor_position.y > 0 => {
+                    // Move the cursor back and remove the character at the cursor position.
+                    self.move_cursor(Key::Left);
+                    self.documents[self.document_index].delete(&self.cursor_position);
+                }
                 // Go into 'view' mode.
                 Key::Esc => self.change_mode(EditorMode::View),
                 // Explanations for each keybinding found in the `move_cursor` function.
@@ -348,21 +1283,33 @@ impl Editor {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_editor()?;
 
-            let key = Terminal::read_key()?;
+            // Other events are still handled while the user is typing into the prompt.
+            let key = match self.events.wait()? {
+                Event::Key(key) => key,
+                // Pasted text is added to the prompt without the line breaks.
+                Event::Paste(text) => {
+                    result.extend(text.chars().filter(|c| !c.is_control()));
+                    Key::Null
+                }
+                event => {
+                    self.process_event(event)?;
+                    // A signal to terminate ends the prompt as if it had been cancelled.
+                    if self.quit {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
             match key {
                 // Remove one character from the prompt result.
-                Key::Backspace => {
-                    if !result.is_empty() {
-                        result.truncate(result.len() - 1);
-                    }
+                Key::Backspace if !result.is_empty() => {
+                    result.truncate(result.len() - 1);
                 }
                 // Since the key is enter, we can stop executing and process the result.
                 Key::Char('\n') => break,
                 // Add a given key to the result prompt.
-                Key::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
-                    }
+                Key::Char(c) if !c.is_control() => {
+                    result.push(c);
                 }
                 // Stop typing and don't submit, this just makes the lenght of the result 0.
                 Key::Esc => {
@@ -387,9 +1334,15 @@ impl Editor {
 
     // Refreshes the editor and checks for a quit signal. If a quit signal is found, stop the
     // execution and else draw all the information on the terminal and flush the screen.
-    fn refresh_editor(&self) -> Result<(), std::io::Error> {
-        // Hide and reset the cursor position and clear the screen.
+    fn refresh_editor(&mut self) -> Result<(), std::io::Error> {
+        // Hide and reset the cursor position and clear the screen. The screen is cleared to the
+        // background of the theme, or to the terminal's own one when quitting.
         Terminal::cursor_hide();
+        if self.quit {
+            Terminal::reset_style();
+        } else {
+            Terminal::set_style(self.theme.text());
+        }
         Terminal::clear_screen();
         Terminal::cursor_position(&Position::default());
 
@@ -398,20 +1351,28 @@ impl Editor {
             Terminal::clear_screen();
             println!("see you later. \r")
         } else {
-            // Draw the rows, status bar and the message bar.
-            self.draw_tildes();
+            // Draw the windows, status bar and the message bar.
+            self.draw_windows();
             self.draw_status_bar();
             self.draw_message_bar();
 
             // Update the terminal cursor position
+            let top = self.window_areas()[self.window_index].start;
             Terminal::cursor_position(&Position {
                 x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+                y: self.cursor_position.y.saturating_sub(self.offset.y) + top,
             });
         }
 
-        // Show the cursor and flush the screen.
-        Terminal::cursor_show();
+        // Show the cursor and flush the screen. The cursor stays hidden if the view has been
+        // scrolled away from it with the mouse.
+        let height = self.window_height();
+        if self.quit
+            || (self.cursor_position.y >= self.offset.y
+                && self.cursor_position.y < self.offset.y.saturating_add(height))
+        {
+            Terminal::cursor_show();
+        }
         Terminal::flush()
     }
 
@@ -420,19 +1381,47 @@ impl Editor {
     // unnamed document without content.
     fn open_new_file(&mut self) {
         let filename = self.prompt("new filepath: ", |_, _, _| {}).unwrap_or(None);
-        let mut final_document = Document::default("");
 
         // Check that the filename is not invalid
-        if filename.is_some() {
-            // Check if we can open a new document using the filename, if not use a default new
-            // document.
-            let new_document = Document::open(&filename.unwrap());
-            if new_document.is_ok() {
-                final_document = new_document.unwrap();
+        match filename {
+            Some(filename) => {
+                self.open_document(&filename);
+            }
+            None => {
+                let mut document = Document::default("");
+                document.set_read_only(self.read_only);
+                self.documents.push(document);
             }
         }
+    }
+
+    // Open a file as a new document, or create a new document with that name if the file doesn't
+    // exist. Returns the index of the document, or None if the file couldn't be opened.
+    fn open_document(&mut self, filename: &str) -> Option<usize> {
+        let mut document = match Document::open(filename) {
+            Ok(document) => document,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                self.status_message = StatusMessage::from(format!("new file {}", filename));
+                Document::default(filename)
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("could not open {}: {}", filename, error));
+                return None;
+            }
+        };
 
-        self.documents.push(final_document);
+        if document.is_hex() {
+            self.status_message = StatusMessage::from(BINARY_MESSAGE.to_string());
+        }
+        if self.read_only {
+            document.set_read_only(true);
+        }
+        self.documents.push(document);
+        let index = self.documents.len() - 1;
+        self.watch_document(index);
+        self.offer_recovery(index);
+        Some(index)
     }
 
     // Move in the list of files by the document index.
@@ -446,47 +1435,107 @@ impl Editor {
         }
     }
 
-    // Create a default instance of an editor.
-    pub fn default() -> Self {
-        // Take a filename as an argument to the program.
-        let args: Vec<String> = env::args().collect();
+    // Create an editor with the documents and options given on the command line.
+    pub fn new(options: Options) -> Self {
         let mut initial_status = String::from("ctrl-q quit | ctrl-s save | ctrl-f search");
-        // create new buffers for each of the arguments
-        let mut documents: Vec<Document> = Vec::new();
-        if args.len() > 1 {
-            for i in 1..args.len() {
-                let file_name = &args[i];
-                let doc = Document::open(&file_name);
-                if doc.is_ok() {
-                    documents.push(doc.unwrap());
-                } else {
-                    initial_status = format!("cound not find {}, creating a new buffer", file_name);
-                    documents.push(Document::default(file_name))
-                }
-            }
+        let documents = open_documents(&options, &mut initial_status);
+        let editor_mode = if options.pager {
+            initial_status = String::from("q quit | space, b page | / search");
+            EditorMode::Pager
         } else {
-            documents.push(Document::default(""));
+            EditorMode::View
+        };
+        // A broken language definition should not go unnoticed, the file is only skipped.
+        if let Some(error) = filetype::load_errors().first() {
+            initial_status = format!("could not load language {}", error);
+        } else if let Some(error) = textmate::load_errors().first() {
+            initial_status = format!("could not load grammar {}", error);
+        }
+        color_depth::set_depth(color_depth::detect());
+        // A theme file named 'default' in the user's themes directory replaces the built-in one.
+        let theme = Theme::load("default").unwrap_or_else(|error| {
+            initial_status = format!("could not load colorscheme {}", error);
+            Theme::default()
+        });
+        let terminal = Terminal::new().expect("failed to initialize terminal");
+        // With '-o' every file gets a window of its own, as many as fit on the screen.
+        let window_count = if options.split {
+            let height = terminal.size().height as usize;
+            documents.len().min(height.saturating_sub(1) / 2).max(1)
+        } else {
+            1
+        };
+        let events = EventLoop::new().expect("failed to initialize event loop");
+        if documents.iter().any(Document::is_stdin) {
+            events.read_stdin();
         }
 
-        Self {
+        let mut editor = Self {
             quit: false,
-            terminal: Terminal::default().expect("failed to initialize terminal"),
+            terminal,
             cursor_position: Position::default(),
             offset: Position::default(),
             status_message: StatusMessage::from(initial_status),
-            editor_mode: EditorMode::View,
-            documents: documents,
+            editor_mode,
+            documents,
             document_index: 0,
             previous_key: termion::event::Key::Null,
+            events,
+            selection: None,
+            checking_files: false,
+            last_query: None,
+            read_only: options.read_only || options.pager,
+            commands: options.commands,
+            theme,
+            windows: (0..window_count)
+                .map(|document_index| Window {
+                    document_index,
+                    cursor_position: Position::default(),
+                    offset: Position::default(),
+                })
+                .collect(),
+            window_index: 0,
+            terminated: false,
+        };
+
+        // Only the first file can be opened at a position, since there is a single cursor. It may
+        // have been left out if it couldn't be opened.
+        let first_name = editor.documents[0].file_name.clone();
+        if let Some(position) = options
+            .files
+            .first()
+            .filter(|file| first_name.as_ref() == Some(&file.name))
+            .and_then(|file| file.position.clone())
+        {
+            editor.go_to(position);
         }
+        editor
+    }
+
+    // Move the cursor to a position, as close as the document allows.
+    fn go_to(&mut self, position: Position) {
+        let height = self.window_height();
+        let document = &mut self.documents[self.document_index];
+        document.load_rows(position.y, height);
+        let len = if position.y >= document.len() {
+            document.count_lines()
+        } else {
+            document.len()
+        };
+        self.cursor_position = Position {
+            x: position.x,
+            y: cmp::min(position.y, len.saturating_sub(1)),
+        };
+        self.move_cursor(Key::Null);
+        self.scroll();
     }
 
     // Handle the mouse scroll.
     pub fn scroll(&mut self) {
         let Position { x, y } = self.cursor_position;
         let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let height = self.window_height();
+        let offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
@@ -500,51 +1549,89 @@ impl Editor {
         }
     }
 
-    // Draw the informative status bar which displays, some helpful commands, and the open
-    // documents.
-    fn draw_status_bar(&self) {
-        let mut status;
-        let width = self.terminal.size().width as usize;
-
+    // Build the left part of the status bar. Also returns the column range of each open document's
+    // name, so that clicking on a name can switch to that document.
+    fn status_bar_text(&self) -> (String, Vec<(Range<usize>, usize)>) {
         // Display a edited message, if the current document is edited without saving.
         let mod_indicator = if self.documents[self.document_index].is_edited() {
             " (edited)"
         } else {
             ""
         };
+        let read_only_indicator = if self.documents[self.document_index].is_read_only() {
+            " [RO]"
+        } else {
+            ""
+        };
 
-        // Display all the open files in the editor.
-        let mut open_document_display = String::new();
-        for document in &self.documents {
-            match &document.file_name {
-                Some(file_name) => open_document_display += &format!(" {}", &file_name).to_string(),
-                None => (),
-            }
-        }
+        // Tell whether the view is following the end of the file or has been paused.
+        let follow_indicator = if !self.documents[self.document_index].is_following() {
+            ""
+        } else if self.follows_end() {
+            " (following)"
+        } else {
+            " (paused)"
+        };
 
         // Display the current opened file.
-        let mut file_name = "[no name]".to_string();
+        let mut file_name = if self.documents[self.document_index].is_stdin() {
+            "[stdin]".to_string()
+        } else {
+            "[no name]".to_string()
+        };
         if let Some(name) = &self.documents[self.document_index].file_name {
             file_name = name.clone();
             file_name.truncate(20);
         }
 
         // Display the editor mode
-        let editor_mode = if self.editor_mode == EditorMode::View {
-            "view".to_string()
-        } else {
-            "insert".to_string()
+        let editor_mode = match self.editor_mode {
+            EditorMode::View => "view".to_string(),
+            EditorMode::Insert => "insert".to_string(),
+            EditorMode::Pager => "pager".to_string(),
         };
-        status = format!(
-            "{} | {}{} | open: {}",
-            editor_mode, file_name, mod_indicator, open_document_display
+        let mut status = format!(
+            "{} | {}{}{}{} | open: ",
+            editor_mode, file_name, read_only_indicator, mod_indicator, follow_indicator
         );
 
+        // Display all the open files in the editor.
+        let mut document_names = Vec::new();
+        for (index, document) in self.documents.iter().enumerate() {
+            if let Some(file_name) = &document.file_name {
+                status.push(' ');
+                let start = status.chars().count();
+                status.push_str(file_name);
+                document_names.push((start..status.chars().count(), index));
+            }
+        }
+
+        (status, document_names)
+    }
+
+    // Describe the number of lines in the current document. The lines of a large file are counted
+    // only as far as the file has been read.
+    fn document_length(&self) -> String {
+        let document = &self.documents[self.document_index];
+        if document.is_fully_loaded() {
+            document.len().to_string()
+        } else {
+            format!("{}+", document.len())
+        }
+    }
+
+    // Draw the informative status bar which displays, some helpful commands, and the open
+    // documents.
+    fn draw_status_bar(&self) {
+        let width = self.terminal.size().width as usize;
+        let (mut status, _) = self.status_bar_text();
+
         // Indicate the current line, max lines and the detected filetype.
         let line_indicator = format!(
-            "[{}/{}] [{}]",
+            "[{}/{}] [{}] [{}]",
             self.cursor_position.y.saturating_add(1),
-            self.documents[self.document_index].len(),
+            self.document_length(),
+            self.documents[self.document_index].file_format(),
             self.documents[self.document_index].file_type(),
         );
         let len = status.len() + line_indicator.len();
@@ -555,18 +1642,17 @@ impl Editor {
 
         // Shorten the statuc to fit the screen and also set the colors.
         status.truncate(width);
-        Terminal::set_bg_color(color::Rgb(255, 255, 255));
-        Terminal::set_fg_color(color::Rgb(63, 63, 63));
+        Terminal::set_style(self.theme.ui("ui.statusbar"));
         println!("{}\r", status);
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+        Terminal::set_style(self.theme.text());
     }
 
     // Draw the message bar.
     fn draw_message_bar(&self) {
+        Terminal::set_style(self.theme.ui("ui.messagebar"));
         Terminal::clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
+        if Instant::now() - message.time < MESSAGE_TIMEOUT {
             let mut text = message.text.clone();
             text.truncate(self.terminal.size().width as usize);
             print!("{}", text);
@@ -587,7 +1673,12 @@ impl Editor {
     }
 
     fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self.terminal.size().height as usize;
+        if self.documents[self.document_index].is_hex() {
+            self.move_hex_cursor(key);
+            return;
+        }
+
+        let terminal_height = self.window_height();
         let Position { mut y, mut x } = self.cursor_position;
         let height = self.documents[self.document_index].len();
         let mut width = if let Some(row) = self.documents[self.document_index].row(y) {
@@ -601,10 +1692,8 @@ impl Editor {
             Key::Up => y = y.saturating_sub(1),
 
             // Move cursor down
-            Key::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
+            Key::Down if y < height => {
+                y = y.saturating_add(1);
             }
 
             // Move cursor left
@@ -632,18 +1721,12 @@ impl Editor {
             }
 
             // Move the cursor by the height of the terminal
-            Key::PageUp => {
-                y = if y > terminal_height {
-                    y - terminal_height
-                } else {
-                    0
-                }
-            }
+            Key::PageUp => y = y.saturating_sub(terminal_height),
 
             // Move the cursor down by the height of the terminal
             Key::PageDown => {
                 y = if y.saturating_add(terminal_height) < height {
-                    y + terminal_height as usize
+                    y + terminal_height
                 } else {
                     height
                 }
@@ -667,36 +1750,210 @@ impl Editor {
         self.cursor_position = Position { x, y }
     }
 
-    // Draw a single row to the terminal screen.
-    pub fn draw_row(&self, row: &Row) {
+    // Move the cursor in the hex view. The cursor moves one nibble at a time and only stops on the
+    // hex digits. Any other key just moves the cursor onto the closest nibble.
+    fn move_hex_cursor(&mut self, key: Key) {
+        let per_row = hex::BYTES_PER_ROW * 2;
+        let terminal_height = self.window_height();
+        let total = self.documents[self.document_index].hex_len() * 2;
+        let Position { x, y } = self.cursor_position;
+        let row_start = y * per_row;
+        let current = row_start + hex::nibble_at_column(x);
+        let last = total.saturating_sub(1);
+
+        let nibble = match key {
+            Key::Left => current.saturating_sub(1),
+            Key::Right => current.saturating_add(1),
+            Key::Up => current.checked_sub(per_row).unwrap_or(current),
+            Key::Down if current + per_row < total => current + per_row,
+            Key::PageUp => current.saturating_sub(per_row * terminal_height),
+            Key::PageDown => current.saturating_add(per_row * terminal_height),
+            Key::Home => row_start,
+            Key::End => row_start + per_row - 1,
+            _ => current,
+        };
+        let nibble = cmp::min(nibble, last);
+
+        self.cursor_position = Position {
+            x: hex::nibble_column(nibble % per_row),
+            y: nibble / per_row,
+        };
+    }
+
+    // Toggle the hex view of the current document.
+    fn toggle_hex(&mut self) {
+        if let Err(error) = self.documents[self.document_index].toggle_hex() {
+            self.status_message = StatusMessage::from(format!("can't show as hex: {}", error));
+            return;
+        }
+        self.move_cursor(Key::Null);
+        self.scroll();
+    }
+
+    // Draw a single row of a window to the terminal screen. The selection is only shown in the
+    // focused window.
+    fn draw_row(&self, row: &Row, y: usize, window: &Window, focused: bool) {
         let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x + width;
-        let row = row.render(start, end);
+        let start = window.offset.x;
+        let end = window.offset.x + width;
+        let selected = if focused {
+            self.selected_columns(row, y)
+        } else {
+            None
+        };
+        let row = row.render(start, end, selected, &self.theme);
 
         println!("{}\r", row)
     }
 
-    // Draw all the rows in a document.
-    fn draw_tildes(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) =
-                self.documents[self.document_index].row(terminal_row as usize + self.offset.y)
-            {
-                self.draw_row(row);
-            } else if self.documents[self.document_index].is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
-            } else {
-                println!("~\r");
+    // Return the part of the row at index y that is inside the selection.
+    fn selected_columns(&self, row: &Row, y: usize) -> Option<Range<usize>> {
+        let (start, end) = self.selection_range()?;
+        if y < start.y || y > end.y {
+            return None;
+        }
+
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y { end.x } else { row.len() };
+        Some(from..to)
+    }
+
+    // Draw the windows with the rows of their documents. Large files only have the rows around
+    // the view loaded, and only the rows in the view are highlighted. If the rows above the view
+    // weren't all highlighted yet, the screen is drawn again soon with more of them done.
+    fn draw_windows(&mut self) {
+        let height = self.terminal.size().height as usize;
+        let areas = self.window_areas();
+        for (index, area) in areas.iter().enumerate() {
+            let window = self.window(index);
+            let document = &mut self.documents[window.document_index];
+            document.load_rows(window.offset.y, area.len());
+            if document.highlight_rows(window.offset.y, area.len()) {
+                self.events.schedule(Timer::Highlight, HIGHLIGHT_DELAY);
+            }
+
+            let document = &self.documents[window.document_index];
+            for terminal_row in 0..area.len() {
+                Terminal::clear_current_line();
+                let y = terminal_row + window.offset.y;
+                if let Some(row) = document.row(y) {
+                    self.draw_row(row, y, &window, index == self.window_index);
+                } else if document.is_empty() && terminal_row == area.len() / 3 {
+                    self.draw_welcome_message();
+                } else {
+                    Terminal::set_style(self.theme.ui("ui.tilde"));
+                    print!("~");
+                    Terminal::set_style(self.theme.text());
+                    println!("\r");
+                }
+            }
+            if index + 1 < areas.len() && area.end < height {
+                self.draw_window_bar(document);
             }
         }
+
+        // Another window may have loaded other rows of the same large file.
+        let window_height = self.window_height();
+        self.documents[self.document_index].load_rows(self.offset.y, window_height);
     }
+
+    // Draw the line below a window, which shows the name of its document.
+    fn draw_window_bar(&self, document: &Document) {
+        let width = self.terminal.size().width as usize;
+        let name = match &document.file_name {
+            Some(name) if !name.is_empty() => name.as_str(),
+            _ if document.is_stdin() => "[stdin]",
+            _ => "[no name]",
+        };
+        let edited = if document.is_edited() {
+            " (edited)"
+        } else {
+            ""
+        };
+        let text: String = format!("{}{}", name, edited).chars().take(width).collect();
+
+        Terminal::clear_current_line();
+        Terminal::set_style(self.theme.ui("ui.statusbar"));
+        println!("{:width$}\r", text, width = width);
+        Terminal::set_style(self.theme.text());
+    }
+}
+
+// Open the documents given on the command line, creating new buffers for the files that don't
+// exist. The pager only shows the documents, so they are read-only as with '-R'.
+fn open_documents(options: &Options, status: &mut String) -> Vec<Document> {
+    let mut documents: Vec<Document> = Vec::new();
+    for file in &options.files {
+        let file_name = &file.name;
+        if file_name == "-" {
+            documents.push(Document::from_stdin());
+            continue;
+        }
+        match Document::open(file_name) {
+            Ok(doc) => {
+                if doc.is_hex() {
+                    *status = BINARY_MESSAGE.to_string();
+                }
+                documents.push(doc);
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                *status = format!("could not find {}, creating a new buffer", file_name);
+                documents.push(Document::default(file_name))
+            }
+            // A file that exists but can't be read is left out, since saving an empty
+            // buffer over it would destroy it.
+            Err(error) => *status = format!("could not open {}: {}", file_name, error),
+        }
+    }
+
+    // Text piped into the editor is shown even without '-', so that it works as '$PAGER'.
+    if documents.is_empty() && !termion::is_tty(&io::stdin()) {
+        documents.push(Document::from_stdin());
+    } else if documents.is_empty() {
+        documents.push(Document::default(""));
+    }
+    if options.read_only || options.pager {
+        for document in &mut documents {
+            document.set_read_only(true);
+        }
+    }
+    documents
 }
 
-// End the execution of the screen.
-fn end(e: std::io::Error) {
-    Terminal::clear_screen();
-    panic!(e);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::FileArgument;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn open_file(pager: bool, read_only: bool) -> Document {
+        let path = env::temp_dir().join(format!(
+            "see-test-{}-{}-{}",
+            process::id(),
+            pager,
+            read_only
+        ));
+        fs::write(&path, "text\n").expect("could not write the test file");
+        let options = Options {
+            files: vec![FileArgument {
+                name: path.to_string_lossy().into_owned(),
+                position: None,
+            }],
+            pager,
+            read_only,
+            ..Options::default()
+        };
+        let mut documents = open_documents(&options, &mut String::new());
+        let _ = fs::remove_file(&path);
+        documents.remove(0)
+    }
+
+    #[test]
+    fn pager_opens_documents_read_only() {
+        assert!(open_file(true, false).is_read_only());
+        assert!(open_file(false, true).is_read_only());
+        assert!(!open_file(false, false).is_read_only());
+    }
 }
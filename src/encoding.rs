@@ -0,0 +1,93 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::io::{Error, ErrorKind};
+
+// How many bytes from the start of a file are looked at when deciding if the file is binary.
+const BINARY_CHECK_LENGTH: usize = 8000;
+
+// The text of a file together with the way it was stored.
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+// Find an encoding by its name, for example 'latin1', 'utf-16le' or 'windows-1252'.
+pub fn from_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+// Return true if the content looks like a binary file instead of text. Text files don't contain
+// null bytes, unless they are in UTF-16.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if Encoding::for_bom(bytes).is_some() {
+        return false;
+    }
+
+    let length = bytes.len().min(BINARY_CHECK_LENGTH);
+    bytes[..length].contains(&0)
+}
+
+// Decode the content of a file. A byte order mark decides the encoding, otherwise the content is
+// read as UTF-8 if it is valid and as Windows-1252 (a superset of Latin-1) if it is not. If an
+// encoding is given, it is used instead of guessing.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> Decoded {
+    let bom = Encoding::for_bom(bytes);
+    let encoding = match (encoding, bom) {
+        (Some(encoding), _) => encoding,
+        (None, Some((encoding, _))) => encoding,
+        (None, None) if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        (None, None) => WINDOWS_1252,
+    };
+
+    // The byte order mark is only removed if it belongs to the encoding in use.
+    let has_bom = matches!(bom, Some((bom_encoding, _)) if bom_encoding == encoding);
+    let content = if has_bom {
+        &bytes[bom.map_or(0, |(_, length)| length)..]
+    } else {
+        bytes
+    };
+    let (text, _) = encoding.decode_without_bom_handling(content);
+
+    Decoded {
+        text: text.into_owned(),
+        encoding,
+        bom: has_bom,
+    }
+}
+
+// Encode text back into the bytes of the given encoding. Characters that don't exist in the
+// encoding are an error instead of being silently replaced.
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little_endian = encoding == UTF_16LE;
+        if bom {
+            bytes.extend(utf16_bytes(0xfeff, little_endian));
+        }
+        for unit in text.encode_utf16() {
+            bytes.extend(utf16_bytes(unit, little_endian));
+        }
+        return Ok(bytes);
+    }
+
+    if bom && encoding == UTF_8 {
+        bytes.extend(b"\xef\xbb\xbf");
+    }
+    let (encoded, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("the text can't be written as {}", encoding.name()),
+        ));
+    }
+    bytes.extend(encoded.iter());
+    Ok(bytes)
+}
+
+fn utf16_bytes(unit: u16, little_endian: bool) -> [u8; 2] {
+    if little_endian {
+        unit.to_le_bytes()
+    } else {
+        unit.to_be_bytes()
+    }
+}
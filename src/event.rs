@@ -0,0 +1,315 @@
+use inotify::{Inotify, WatchMask, Watches};
+use signal_hook::consts::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP, SIGWINCH};
+use signal_hook::iterator::Signals;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Error};
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::event::{Event as TermEvent, Key, MouseEvent};
+use termion::input::TermReadEventsAndRaw;
+
+// The terminal wraps pasted text between these sequences when bracketed paste is enabled.
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+// How many bytes of the standard input are collected before they are sent to the editor.
+const STDIN_BATCH: usize = 64 * 1024;
+
+// Everything that can wake up the editor's main loop.
+pub enum Event {
+    Key(Key),
+    Mouse(MouseEvent),
+    Paste(String),
+    Timer(Timer),
+    Signal(Signal),
+    Job(JobOutput),
+    FileChanged,
+    Stdin(String),
+}
+
+// The different timers the editor can schedule. Each timer can only be pending once, scheduling it
+// again moves its deadline.
+#[derive(PartialEq, Clone, Copy)]
+pub enum Timer {
+    StatusMessage,
+    SwapFiles,
+    FileCheck,
+    Highlight, // Highlights more of the rows above the view
+}
+
+// The signals the editor reacts to.
+pub enum Signal {
+    Resize,
+    Suspend,
+    Continue,
+    Terminate,
+}
+
+// The result of a background job once the job has finished.
+pub struct JobOutput {
+    pub command: String,
+    pub output: Result<Output, Error>,
+}
+
+// The event loop multiplexes the terminal input, timers, signals and background jobs into a single
+// stream of events. Input, signals and jobs are all read in their own threads and sent over a
+// channel, the timers are handled by waiting on that channel with a timeout.
+pub struct EventLoop {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+    timers: Vec<(Timer, Instant)>,
+    watches: Option<Watches>, // Missing if inotify is not available
+    watched: Arc<Mutex<HashSet<OsString>>>, // The names of the watched files
+}
+
+impl EventLoop {
+    pub fn new() -> Result<Self, Error> {
+        let (sender, receiver) = mpsc::channel();
+
+        // Read the keys and mouse events from the terminal device instead of standard input, this
+        // way the blocking read never stops the editor from handling other events.
+        let tty = termion::get_tty()?;
+        let input = sender.clone();
+        thread::spawn(move || {
+            // The bytes of a bracketed paste are collected until the paste ends.
+            let mut paste: Option<Vec<u8>> = None;
+            for event in tty.events_and_raw() {
+                let (event, raw) = match event {
+                    Ok(event) => event,
+                    Err(_) => return,
+                };
+                let event = match (event, paste.as_mut()) {
+                    (TermEvent::Unsupported(ref sequence), None) if sequence == PASTE_START => {
+                        paste = Some(Vec::new());
+                        continue;
+                    }
+                    (TermEvent::Unsupported(ref sequence), Some(_)) if sequence == PASTE_END => {
+                        let bytes = paste.take().unwrap_or_default();
+                        Event::Paste(pasted_text(&bytes))
+                    }
+                    (_, Some(bytes)) => {
+                        bytes.extend(raw);
+                        continue;
+                    }
+                    (TermEvent::Key(key), None) => Event::Key(key),
+                    (TermEvent::Mouse(mouse), None) => Event::Mouse(mouse),
+                    (TermEvent::Unsupported(_), None) => continue,
+                };
+                if input.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut signals = Signals::new([SIGWINCH, SIGTSTP, SIGCONT, SIGTERM, SIGHUP])?;
+        let signal_sender = sender.clone();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let event = match signal {
+                    SIGWINCH => Event::Signal(Signal::Resize),
+                    SIGTSTP => Event::Signal(Signal::Suspend),
+                    SIGCONT => Event::Signal(Signal::Continue),
+                    SIGTERM | SIGHUP => Event::Signal(Signal::Terminate),
+                    _ => continue,
+                };
+                if signal_sender.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // Files are watched through their directories, because many programs replace a file by
+        // renaming a new file over it instead of writing into it.
+        let watched: Arc<Mutex<HashSet<OsString>>> = Arc::default();
+        let watches = Inotify::init().ok().map(|mut inotify| {
+            let watches = inotify.watches();
+            let names = Arc::clone(&watched);
+            let file_sender = sender.clone();
+            thread::spawn(move || {
+                let mut buffer = [0; 4096];
+                while let Ok(events) = inotify.read_events_blocking(&mut buffer) {
+                    let changed = {
+                        let names = names.lock().unwrap_or_else(|error| error.into_inner());
+                        events
+                            .filter_map(|event| event.name)
+                            .any(|name| names.contains(name))
+                    };
+                    if changed && file_sender.send(Event::FileChanged).is_err() {
+                        return;
+                    }
+                }
+            });
+            watches
+        });
+
+        Ok(Self {
+            sender,
+            receiver,
+            timers: Vec::new(),
+            watches,
+            watched,
+        })
+    }
+
+    // Schedule a timer to fire after the given duration.
+    pub fn schedule(&mut self, timer: Timer, after: Duration) {
+        let deadline = Instant::now() + after;
+        self.timers.retain(|(pending, _)| *pending != timer);
+        self.timers.push((timer, deadline));
+    }
+
+    // Run a shell command in the background. The output is delivered as an event once the command
+    // has finished.
+    pub fn spawn_job(&self, command: &str) {
+        let sender = self.sender.clone();
+        let command = command.to_string();
+        thread::spawn(move || {
+            let output = Command::new("sh").arg("-c").arg(&command).output();
+            let _ = sender.send(Event::Job(JobOutput { command, output }));
+        });
+    }
+
+    // Read the standard input in the background. The text is delivered a line at a time, or in
+    // larger pieces if more is available at once.
+    pub fn read_stdin(&self) {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::with_capacity(STDIN_BATCH, io::stdin());
+            let mut batch = Vec::new();
+            loop {
+                match reader.read_until(b'\n', &mut batch) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if batch.len() < STDIN_BATCH && !reader.buffer().is_empty() => continue,
+                    Ok(_) => (),
+                }
+                let text = strip_escapes(&String::from_utf8_lossy(&batch));
+                if sender.send(Event::Stdin(text)).is_err() {
+                    return;
+                }
+                batch.clear();
+            }
+            if !batch.is_empty() {
+                let text = strip_escapes(&String::from_utf8_lossy(&batch));
+                let _ = sender.send(Event::Stdin(text));
+            }
+        });
+    }
+
+    // Watch a file for changes made by other programs. Returns false if the file can't be watched,
+    // in which case the caller has to check the file by itself.
+    pub fn watch(&mut self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let (watches, name) = match (self.watches.as_mut(), path.file_name()) {
+            (Some(watches), Some(name)) => (watches, name),
+            _ => return false,
+        };
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mask = WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::CREATE
+            | WatchMask::DELETE;
+        if watches.add(directory, mask).is_err() {
+            return false;
+        }
+
+        let mut watched = self
+            .watched
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        watched.insert(name.to_os_string());
+        true
+    }
+
+    // Block until the next event happens. Timers that have expired are returned before any other
+    // events.
+    pub fn wait(&mut self) -> Result<Event, Error> {
+        loop {
+            let now = Instant::now();
+            if let Some(index) = self
+                .timers
+                .iter()
+                .position(|(_, deadline)| *deadline <= now)
+            {
+                let (timer, _) = self.timers.remove(index);
+                return Ok(Event::Timer(timer));
+            }
+
+            // Wait for the channel only until the closest timer should fire.
+            let closest = self.timers.iter().map(|(_, deadline)| *deadline).min();
+            let event = match closest {
+                Some(deadline) => match self.receiver.recv_timeout(deadline - now) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return Err(disconnected()),
+                },
+                None => self.receiver.recv().map_err(|_| disconnected())?,
+            };
+
+            return Ok(event);
+        }
+    }
+}
+
+// Turn the raw bytes of a paste into text. Terminals send line breaks as carriage returns.
+fn pasted_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+}
+
+// Remove the escape sequences from text, for example the colors in the output of 'git log'. The
+// sequences would otherwise be written to the terminal as they are.
+fn strip_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // A control sequence ends with a character between '@' and '~'.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating system commands, such as a link or the title of the window, and the other
+            // strings end with a bell or with the string terminator 'ESC \'.
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next_if_eq(&'\\');
+                        break;
+                    }
+                }
+            }
+            // Other escapes may have intermediate characters between ' ' and '/' before their
+            // final character, as in 'ESC ( B', and are otherwise just two characters long.
+            Some(' '..='/') => {
+                while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+                chars.next();
+            }
+            _ => (),
+        }
+    }
+    stripped
+}
+
+fn disconnected() -> Error {
+    Error::other("event channel disconnected")
+}
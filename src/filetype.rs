@@ -1,23 +1,117 @@
+use crate::textmate;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use syntect::parsing::SyntaxReference;
+
+// The language definitions built into the editor. A definition with the same name in the user's
+// languages directory replaces the built-in one.
+const BUILT_IN: [(&str, &str); 15] = [
+    ("rust.toml", include_str!("../language_keywords/rust.toml")),
+    (
+        "python.toml",
+        include_str!("../language_keywords/python.toml"),
+    ),
+    ("go.toml", include_str!("../language_keywords/go.toml")),
+    ("c.toml", include_str!("../language_keywords/c.toml")),
+    ("cpp.toml", include_str!("../language_keywords/cpp.toml")),
+    (
+        "shell.toml",
+        include_str!("../language_keywords/shell.toml"),
+    ),
+    ("make.toml", include_str!("../language_keywords/make.toml")),
+    (
+        "dockerfile.toml",
+        include_str!("../language_keywords/dockerfile.toml"),
+    ),
+    ("lua.toml", include_str!("../language_keywords/lua.toml")),
+    ("sql.toml", include_str!("../language_keywords/sql.toml")),
+    ("lisp.toml", include_str!("../language_keywords/lisp.toml")),
+    ("tex.toml", include_str!("../language_keywords/tex.toml")),
+    ("ini.toml", include_str!("../language_keywords/ini.toml")),
+    ("toml.toml", include_str!("../language_keywords/toml.toml")),
+    ("yaml.toml", include_str!("../language_keywords/yaml.toml")),
+];
+
+// How many lines at the start and at the end of a file are searched for a modeline.
+const MODELINE_LINES: usize = 5;
+
+// How many lines at the start of a file are searched for the content markers of a language.
+const MARKER_LINES: usize = 500;
+
+static LANGUAGES: OnceLock<Languages> = OnceLock::new();
+
 pub struct FileType {
     name: String,
     highlight_opts: HighlightOptions,
+    grammar: Option<&'static SyntaxReference>, // The TextMate grammar the file is highlighted by
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Deserialize)]
+#[serde(default)]
 pub struct HighlightOptions {
     numbers: bool,
     strings: bool,
     characters: bool,
     comments: bool,
+    // The tokens that start a comment running to the end of the line.
+    line_comments: Vec<String>,
+    // The characters that start and end a string.
+    string_delimiters: Vec<char>,
+    // The tokens that start and end a block comment, and whether those can be inside each other.
+    block_comments: Vec<(String, String)>,
+    nested_comments: bool,
+    // The delimiters of strings that can span rows, and of the same without escapes, such as
+    // Go's backticks.
+    multiline_strings: Vec<(String, String)>,
+    raw_strings: Vec<(String, String)>,
+    // Whether there are raw strings such as r#"..."#.
+    hash_raw_strings: bool,
+    // Whether a quote before a name is a lifetime, as in Rust, when it isn't a character.
+    lifetimes: bool,
+    // The prefixes of attributes and decorators. A prefix ending with '[' goes on to the closing
+    // bracket, and other prefixes are followed by a name.
+    attributes: Vec<String>,
+    // Whether a '#' at the start of a row starts a preprocessor directive.
+    preprocessor: bool,
     primary_keywords: Vec<String>,
     secondary_keywords: Vec<String>,
 }
 
+// A language as it is written in a definition file.
+#[derive(Deserialize)]
+struct Language {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>, // Other names of the language, as used in modelines
+    #[serde(default)]
+    extensions: Vec<String>, // The file extensions without the dot
+    #[serde(default)]
+    filenames: Vec<String>, // Whole file names, such as "Makefile"
+    #[serde(default)]
+    globs: Vec<String>, // Patterns of file names, such as "Dockerfile.*"
+    #[serde(default)]
+    shebangs: Vec<String>, // The interpreters named on the first line of a script
+    #[serde(default)]
+    content_markers: Vec<String>, // Text that picks this language when others share the extension
+    #[serde(flatten)]
+    options: HighlightOptions,
+}
+
+// Every known language, together with the problems found while reading the definition files.
+struct Languages {
+    languages: Vec<Language>,
+    errors: Vec<String>,
+}
+
 impl Default for FileType {
     fn default() -> Self {
         Self {
             name: String::from("No filetype"),
             highlight_opts: HighlightOptions::default(),
+            grammar: None,
         }
     }
 }
@@ -31,305 +125,75 @@ impl FileType {
         &self.highlight_opts
     }
 
-    pub fn from(file_name: &str) -> Self {
-        if file_name.ends_with(".rs") {
-            return Self {
-                name: String::from("rust"),
-                highlight_opts: HighlightOptions {
-                    numbers: false,
-                    strings: false,
-                    characters: false,
-                    comments: true,
-                    primary_keywords: vec![
-                        "as".to_string(),
-                        "break".to_string(),
-                        "const".to_string(),
-                        "continue".to_string(),
-                        "crate".to_string(),
-                        "else".to_string(),
-                        "enum".to_string(),
-                        "extern".to_string(),
-                        "false".to_string(),
-                        "fn".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "impl".to_string(),
-                        "in".to_string(),
-                        "let".to_string(),
-                        "loop".to_string(),
-                        "match".to_string(),
-                        "mod".to_string(),
-                        "move".to_string(),
-                        "mut".to_string(),
-                        "pub".to_string(),
-                        "ref".to_string(),
-                        "return".to_string(),
-                        "self".to_string(),
-                        "Self".to_string(),
-                        "static".to_string(),
-                        "struct".to_string(),
-                        "super".to_string(),
-                        "trait".to_string(),
-                        "true".to_string(),
-                        "type".to_string(),
-                        "unsafe".to_string(),
-                        "use".to_string(),
-                        "where".to_string(),
-                        "while".to_string(),
-                        "dyn".to_string(),
-                        "abstract".to_string(),
-                        "become".to_string(),
-                        "box".to_string(),
-                        "do".to_string(),
-                        "final".to_string(),
-                        "macro".to_string(),
-                        "override".to_string(),
-                        "priv".to_string(),
-                        "typeof".to_string(),
-                        "unsized".to_string(),
-                        "virtual".to_string(),
-                        "yield".to_string(),
-                        "async".to_string(),
-                        "await".to_string(),
-                        "try".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_string(),
-                        "char".to_string(),
-                        "i8".to_string(),
-                        "i16".to_string(),
-                        "i32".to_string(),
-                        "i64".to_string(),
-                        "isize".to_string(),
-                        "u8".to_string(),
-                        "u16".to_string(),
-                        "u32".to_string(),
-                        "u64".to_string(),
-                        "usize".to_string(),
-                        "f32".to_string(),
-                        "f64".to_string(),
-                    ],
-                },
-            };
-        }
+    pub fn grammar(&self) -> Option<&'static SyntaxReference> {
+        self.grammar
+    }
 
-        if file_name.ends_with(".py") {
-            return Self {
-                name: String::from("python3"),
-                highlight_opts: HighlightOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    primary_keywords: vec![
-                        "class".to_string(),
-                        "def".to_string(),
-                        "else".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "global".to_string(),
-                        "while".to_string(),
-                        "return".to_string(),
-                        "pass".to_string(),
-                        "import".to_string(),
-                        "try".to_string(),
-                        "except".to_string(),
-                        "finally".to_string(),
-                        "async".to_string(),
-                        "await".to_string(),
-                        "elif".to_string(),
-                        "raise".to_string(),
-                        "with".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "True".to_string(),
-                        "False".to_string(),
-                        "None".to_string(),
-                        "and".to_string(),
-                        "as".to_string(),
-                        "assert".to_string(),
-                        "break".to_string(),
-                        "continue".to_string(),
-                        "del".to_string(),
-                        "from".to_string(),
-                        "in".to_string(),
-                        "is".to_string(),
-                        "lambda".to_string(),
-                        "nonlocal".to_string(),
-                        "not".to_string(),
-                        "or".to_string(),
-                        "yield".to_string(),
-                    ],
-                },
-            };
-        }
+    // Detect the filetype of a file from its name and the text in it. A modeline wins over the
+    // name of the file, and the first line of a script is only looked at when the name doesn't
+    // tell the filetype. The grammars in the syntaxes directory are only used for files that no
+    // language is found for.
+    pub fn from(file_name: &str, text: &str) -> Self {
+        let languages = &languages().languages;
+        let path = Path::new(file_name);
+        let base_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let extension = path.extension().and_then(|extension| extension.to_str());
 
-        if file_name.ends_with(".go") {
-            return Self {
-                name: String::from("golang"),
-                highlight_opts: HighlightOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    primary_keywords: vec![
-                        "break".to_string(),
-                        "default".to_string(),
-                        "func".to_string(),
-                        "interface".to_string(),
-                        "select".to_string(),
-                        "case".to_string(),
-                        "defer".to_string(),
-                        "go".to_string(),
-                        "map".to_string(),
-                        "struct".to_string(),
-                        "chan".to_string(),
-                        "else".to_string(),
-                        "goto".to_string(),
-                        "package".to_string(),
-                        "switch".to_string(),
-                        "const".to_string(),
-                        "fallthrough".to_string(),
-                        "if".to_string(),
-                        "range".to_string(),
-                        "type".to_string(),
-                        "continue".to_string(),
-                        "for".to_string(),
-                        "import".to_string(),
-                        "return".to_string(),
-                        "var".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_string(),
-                        "string".to_string(),
-                        "int".to_string(),
-                        "int8".to_string(),
-                        "int16".to_string(),
-                        "int32".to_string(),
-                        "int64".to_string(),
-                        "uint".to_string(),
-                        "uint8".to_string(),
-                        "uint16".to_string(),
-                        "uint32".to_string(),
-                        "uint64".to_string(),
-                        "uintptr".to_string(),
-                        "byte".to_string(),
-                        "rune".to_string(),
-                        "float32".to_string(),
-                        "float64".to_string(),
-                        "complex64".to_string(),
-                        "complex128".to_string(),
-                    ],
-                },
-            };
+        let language = modeline(text)
+            .and_then(find_language)
+            .or_else(|| {
+                languages
+                    .iter()
+                    .find(|language| language.filenames.iter().any(|name| name == base_name))
+            })
+            .or_else(|| {
+                languages.iter().find(|language| {
+                    language
+                        .globs
+                        .iter()
+                        .any(|glob| glob_matches(glob, base_name))
+                })
+            })
+            .or_else(|| extension.and_then(|extension| by_extension(extension, text)))
+            .or_else(|| shebang(text).and_then(by_shebang));
+        language
+            .map(FileType::of)
+            .or_else(|| {
+                modeline(text)
+                    .and_then(textmate::named)
+                    .or_else(|| textmate::find(file_name, text))
+                    .map(FileType::of_grammar)
+            })
+            .unwrap_or_default()
+    }
+
+    // Return the filetype with the given name or alias, "none" being no filetype at all.
+    pub fn named(name: &str) -> Option<Self> {
+        if name == "none" {
+            return Some(Self::default());
         }
+        find_language(name)
+            .map(FileType::of)
+            .or_else(|| textmate::named(name).map(FileType::of_grammar))
+    }
 
-        if file_name.ends_with(".cpp")
-            || file_name.ends_with(".h")
-            || file_name.ends_with(".c")
-            || file_name.ends_with(".hpp")
-        {
-            return Self {
-                name: String::from("c/cpp"),
-                highlight_opts: HighlightOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    primary_keywords: vec![
-                        "alingas".to_string(),
-                        "alingof".to_string(),
-                        "and".to_string(),
-                        "and_eq".to_string(),
-                        "asm".to_string(),
-                        "bitand".to_string(),
-                        "bitor".to_string(),
-                        "break".to_string(),
-                        "case".to_string(),
-                        "catch".to_string(),
-                        "class".to_string(),
-                        "compl".to_string(),
-                        "conecpt".to_string(),
-                        "const".to_string(),
-                        "const_cast".to_string(),
-                        "consteval".to_string(),
-                        "constexpr".to_string(),
-                        "constinit".to_string(),
-                        "continue".to_string(),
-                        "co_await".to_string(),
-                        "co_return".to_string(),
-                        "co_yield".to_string(),
-                        "decltype".to_string(),
-                        "default".to_string(),
-                        "delete".to_string(),
-                        "do".to_string(),
-                        "double".to_string(),
-                        "dynamic_cast".to_string(),
-                        "else".to_string(),
-                        "enum".to_string(),
-                        "explicit".to_string(),
-                        "export".to_string(),
-                        "exportfalse".to_string(),
-                        "for".to_string(),
-                        "friend".to_string(),
-                        "goto".to_string(),
-                        "if".to_string(),
-                        "inline".to_string(),
-                        "mutable".to_string(),
-                        "namespace".to_string(),
-                        "new".to_string(),
-                        "noexcpet".to_string(),
-                        "notnot_eq".to_string(),
-                        "operator".to_string(),
-                        "or_eq".to_string(),
-                        "private".to_string(),
-                        "protected".to_string(),
-                        "public".to_string(),
-                        "register".to_string(),
-                        "reinterpret_cast".to_string(),
-                        "requries".to_string(),
-                        "return".to_string(),
-                        "signed".to_string(),
-                        "sizeof".to_string(),
-                        "statis".to_string(),
-                        "statis_assert".to_string(),
-                        "static_cast".to_string(),
-                        "switch".to_string(),
-                        "this".to_string(),
-                        "thread_local".to_string(),
-                        "throw".to_string(),
-                        "try".to_string(),
-                        "typedef".to_string(),
-                        "typeid".to_string(),
-                        "using".to_string(),
-                        "virtual".to_string(),
-                        "volative".to_string(),
-                        "while".to_string(),
-                        "xor".to_string(),
-                        "xor_eq".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "void".to_string(),
-                        "bool".to_string(),
-                        "true".to_string(),
-                        "false".to_string(),
-                        "char".to_string(),
-                        "wchar_t".to_string(),
-                        "char8_t".to_string(),
-                        "char16_t".to_string(),
-                        "char32_t".to_string(),
-                        "size_t".to_string(),
-                        "int".to_string(),
-                        "short".to_string(),
-                        "long".to_string(),
-                        "unsigned".to_string(),
-                        "float".to_string(),
-                    ],
-                },
-            };
+    fn of(language: &Language) -> Self {
+        Self {
+            name: language.name.clone(),
+            highlight_opts: language.options.clone(),
+            grammar: None,
         }
+    }
 
-        Self::default()
+    fn of_grammar(grammar: &'static SyntaxReference) -> Self {
+        Self {
+            name: grammar.name.clone(),
+            highlight_opts: HighlightOptions::default(),
+            grammar: Some(grammar),
+        }
     }
 }
 
@@ -350,6 +214,46 @@ impl HighlightOptions {
         self.comments
     }
 
+    pub fn line_comments(&self) -> &Vec<String> {
+        &self.line_comments
+    }
+
+    pub fn string_delimiters(&self) -> &Vec<char> {
+        &self.string_delimiters
+    }
+
+    pub fn block_comments(&self) -> &Vec<(String, String)> {
+        &self.block_comments
+    }
+
+    pub fn nested_comments(&self) -> bool {
+        self.nested_comments
+    }
+
+    pub fn multiline_strings(&self) -> &Vec<(String, String)> {
+        &self.multiline_strings
+    }
+
+    pub fn raw_strings(&self) -> &Vec<(String, String)> {
+        &self.raw_strings
+    }
+
+    pub fn hash_raw_strings(&self) -> bool {
+        self.hash_raw_strings
+    }
+
+    pub fn lifetimes(&self) -> bool {
+        self.lifetimes
+    }
+
+    pub fn attributes(&self) -> &Vec<String> {
+        &self.attributes
+    }
+
+    pub fn preprocessor(&self) -> bool {
+        self.preprocessor
+    }
+
     pub fn primary_keywords(&self) -> &Vec<String> {
         &self.primary_keywords
     }
@@ -358,3 +262,252 @@ impl HighlightOptions {
         &self.secondary_keywords
     }
 }
+
+// Return the problems found in the language definition files.
+pub fn load_errors() -> &'static [String] {
+    &languages().errors
+}
+
+fn languages() -> &'static Languages {
+    LANGUAGES.get_or_init(load_languages)
+}
+
+// Find a language by its name or one of its aliases, ignoring case.
+fn find_language(name: &str) -> Option<&'static Language> {
+    languages().languages.iter().find(|language| {
+        language.name.eq_ignore_ascii_case(name)
+            || language
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+// Find the language of a file extension. When several languages share the extension, as C and C++
+// share ".h", the first one whose content markers appear near the start of the file is chosen, and
+// otherwise the first one without any markers.
+fn by_extension(extension: &str, text: &str) -> Option<&'static Language> {
+    let candidates: Vec<&Language> = languages()
+        .languages
+        .iter()
+        .filter(|language| language.extensions.iter().any(|other| other == extension))
+        .collect();
+    if candidates.len() > 1 {
+        let marked = candidates.iter().find(|language| {
+            text.lines().take(MARKER_LINES).any(|line| {
+                language
+                    .content_markers
+                    .iter()
+                    .any(|marker| line.contains(marker))
+            })
+        });
+        if let Some(language) = marked {
+            return Some(language);
+        }
+    }
+
+    candidates
+        .iter()
+        .find(|language| language.content_markers.is_empty())
+        .or(candidates.first())
+        .copied()
+}
+
+// Find the language of a script from its interpreter. A version after the name is ignored, so that
+// "python3.12" is found as "python3".
+fn by_shebang(interpreter: &str) -> Option<&'static Language> {
+    languages().languages.iter().find(|language| {
+        language.shebangs.iter().any(|name| {
+            interpreter
+                .strip_prefix(name.as_str())
+                .is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        })
+    })
+}
+
+// Return the name of the interpreter on the first line of a script, as in "#!/bin/sh" or
+// "#!/usr/bin/env python3".
+fn shebang(text: &str) -> Option<&str> {
+    let line = text.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let program = Path::new(words.next()?).file_name()?.to_str()?;
+    if program != "env" {
+        return Some(program);
+    }
+    // The options of env, such as "-S", come before the interpreter.
+    words.find(|word| !word.starts_with('-') && !word.contains('='))
+}
+
+// Find the filetype set by a modeline near the start or the end of the text, as in
+// "vim: set ft=python:" or "-*- mode: python -*-".
+fn modeline(text: &str) -> Option<&str> {
+    text.lines()
+        .take(MODELINE_LINES)
+        .chain(text.lines().rev().take(MODELINE_LINES))
+        .find_map(|line| vim_modeline(line).or_else(|| emacs_modeline(line)))
+}
+
+fn vim_modeline(line: &str) -> Option<&str> {
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(index, _)| *index == 0 || line[..*index].ends_with(char::is_whitespace))
+            .map(|(index, _)| index + marker.len())
+    })?;
+    line[start..]
+        .split([' ', '\t', ':'])
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+        })
+        .filter(|name| !name.is_empty())
+}
+
+fn emacs_modeline(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (variables, _) = rest.split_once("-*-")?;
+    // A line with only a mode in it, as in "-*- python -*-", has no variables.
+    if !variables.contains(':') {
+        return Some(variables.trim()).filter(|name| !name.is_empty());
+    }
+    variables.split(';').find_map(|variable| {
+        let (name, value) = variable.split_once(':')?;
+        (name.trim() == "mode").then(|| value.trim())
+    })
+}
+
+// Match a file name against a pattern, where '*' matches any text and '?' a single character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_from(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_from(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && matches_from(rest, &name[1..]),
+    }
+}
+
+// Read the built-in definitions and then the user's own ones. The user's definitions come first,
+// so that they are preferred when two languages claim the same extension.
+fn load_languages() -> Languages {
+    let mut languages = Vec::new();
+    let mut errors = Vec::new();
+
+    for (file_name, text) in BUILT_IN {
+        match toml::from_str(text) {
+            Ok(language) => languages.push(language),
+            Err(error) => errors.push(format!("{}: {}", file_name, error.message())),
+        }
+    }
+
+    for language in user_languages(&mut errors).into_iter().rev() {
+        languages.retain(|other: &Language| other.name != language.name);
+        languages.insert(0, language);
+    }
+
+    Languages { languages, errors }
+}
+
+// Return the directory of the user's configuration, which has the languages and the grammars in
+// it.
+#[cfg(not(test))]
+pub fn config_directory() -> Option<PathBuf> {
+    let config_directory = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_directory.join("see"))
+}
+
+// The tests use a directory that doesn't exist, so that they only see the built-in definitions and
+// not whatever the user has configured.
+#[cfg(test)]
+pub fn config_directory() -> Option<PathBuf> {
+    Some(env::temp_dir().join(format!("see-test-config-{}", std::process::id())))
+}
+
+// Read the definitions in the user's languages directory in the order of their file names. A
+// missing directory is not an error.
+fn user_languages(errors: &mut Vec<String>) -> Vec<Language> {
+    let directory = config_directory().map(|directory| directory.join("languages"));
+    let entries = match directory.map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml")
+        })
+        .collect();
+    paths.sort();
+
+    let mut languages = Vec::new();
+    for path in paths {
+        let result = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|error| error.message().to_string()));
+        match result {
+            Ok(language) => languages.push(language),
+            Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+        }
+    }
+    languages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(file_name: &str, text: &str) -> String {
+        FileType::from(file_name, text).name()
+    }
+
+    #[test]
+    fn only_built_in_languages() {
+        let mut errors = Vec::new();
+        assert!(user_languages(&mut errors).is_empty());
+        assert!(errors.is_empty());
+        assert_eq!(languages().languages.len(), BUILT_IN.len());
+    }
+
+    #[test]
+    fn by_extension_and_name() {
+        assert_eq!(detect("src/main.rs", ""), "rust");
+        assert_eq!(detect("script.py", ""), "python3");
+        assert_eq!(detect("build/Makefile", ""), "make");
+        assert_eq!(detect("notes", "plain text\n"), "No filetype");
+    }
+
+    #[test]
+    fn shared_extension_by_content() {
+        assert_eq!(detect("point.h", "struct point { int x; };\n"), "c");
+        assert_eq!(detect("point.h", "namespace geometry {\n}\n"), "cpp");
+    }
+
+    #[test]
+    fn by_shebang() {
+        assert_eq!(detect("run", "#!/bin/bash\necho hi\n"), "shell");
+        assert_eq!(detect("run", "#!/usr/bin/env python3.12\n"), "python3");
+        assert_eq!(detect("run", "#!/usr/bin/env -S python3 -u\n"), "python3");
+        // The name of the file wins over the interpreter.
+        assert_eq!(detect("run.rs", "#!/bin/sh\n"), "rust");
+    }
+
+    #[test]
+    fn modeline_wins() {
+        assert_eq!(detect("config", "# vim: set ft=python:\n"), "python3");
+        assert_eq!(detect("main.c", "// -*- mode: c++ -*-\n"), "cpp");
+        assert_eq!(detect("main.c", "/* -*- rust -*- */\n"), "rust");
+        assert_eq!(detect("main.rs", "fn main() {}\n// vi: ft=sh\n"), "shell");
+        // A word that only ends with "vim:" is not a modeline.
+        assert_eq!(detect("main.rs", "// myvim: ft=sh\n"), "rust");
+    }
+}
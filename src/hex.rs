@@ -0,0 +1,130 @@
+use crate::Row;
+use std::cmp;
+
+// How many bytes are shown on a single row of the hex view.
+pub const BYTES_PER_ROW: usize = 16;
+
+// The number of hex digits in the offset column, which two spaces separate from the bytes.
+const OFFSET_DIGITS: usize = 8;
+const FIRST_BYTE_COLUMN: usize = OFFSET_DIGITS + 2;
+
+// Each byte takes two digits and a space, and another space splits the bytes into two groups. A
+// space and a bar come before the ASCII pane.
+const BAR_COLUMN: usize = FIRST_BYTE_COLUMN + BYTES_PER_ROW * 3 + 2;
+const ASCII_COLUMN: usize = BAR_COLUMN + 1;
+
+// Return the column of a nibble on a row of the hex view. The nibbles of a row are numbered from
+// zero, two for each byte with the high nibble first.
+pub fn nibble_column(nibble: usize) -> usize {
+    let byte = nibble / 2;
+    let gap = if byte >= BYTES_PER_ROW / 2 { 1 } else { 0 };
+    FIRST_BYTE_COLUMN + byte * 3 + gap + nibble % 2
+}
+
+// Return the nibble closest to the given column. Columns in the ASCII pane map to the high nibble
+// of the byte they show.
+pub fn nibble_at_column(column: usize) -> usize {
+    if column >= ASCII_COLUMN {
+        return cmp::min(column - ASCII_COLUMN, BYTES_PER_ROW - 1) * 2;
+    }
+
+    (0..BYTES_PER_ROW * 2)
+        .rev()
+        .find(|nibble| nibble_column(*nibble) <= column)
+        .unwrap_or(0)
+}
+
+// Parse a search query given as hex, for example "de ad be ef" or "deadbeef".
+pub fn parse_bytes(query: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u32> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+            .collect(),
+    )
+}
+
+// Format a single row of the hex view: the offset of the first byte, the bytes in hex and the same
+// bytes as ASCII, where the bytes that can't be printed are shown as dots.
+pub fn dump_line(offset: usize, bytes: &[u8]) -> String {
+    let mut line = format!("{:0width$x}  ", offset, width = OFFSET_DIGITS);
+    for index in 0..BYTES_PER_ROW {
+        match bytes.get(index) {
+            Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+            None => line.push_str("   "),
+        }
+        // Split the bytes into two groups of eight to make them easier to count.
+        if index + 1 == BYTES_PER_ROW / 2 {
+            line.push(' ');
+        }
+    }
+
+    line.push_str(" |");
+    for byte in bytes {
+        if byte.is_ascii_graphic() || *byte == b' ' {
+            line.push(*byte as char);
+        } else {
+            line.push('.');
+        }
+    }
+    line.push('|');
+    line
+}
+
+// Turn the whole content into the rows of the hex view.
+pub fn dump_rows(bytes: &[u8]) -> Vec<Row> {
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(index, chunk)| Row::from(&dump_line(index * BYTES_PER_ROW, chunk)[..]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let line: Vec<char> = dump_line(0x20, b"0123456789abcdef").chars().collect();
+        assert_eq!(line[FIRST_BYTE_COLUMN..FIRST_BYTE_COLUMN + 2], ['3', '0']);
+        assert_eq!(line[nibble_column(31)], '6');
+        assert_eq!(line[BAR_COLUMN], '|');
+        assert_eq!(line[ASCII_COLUMN], '0');
+        assert_eq!(line[ASCII_COLUMN + BYTES_PER_ROW - 1], 'f');
+    }
+
+    #[test]
+    fn clicking_the_ascii_pane() {
+        assert_eq!(nibble_at_column(ASCII_COLUMN), 0);
+        assert_eq!(nibble_at_column(ASCII_COLUMN + 1), 2);
+        assert_eq!(
+            nibble_at_column(ASCII_COLUMN + BYTES_PER_ROW - 1),
+            (BYTES_PER_ROW - 1) * 2
+        );
+        // The closing bar and anything after it belong to the last byte.
+        assert_eq!(
+            nibble_at_column(ASCII_COLUMN + BYTES_PER_ROW + 5),
+            (BYTES_PER_ROW - 1) * 2
+        );
+    }
+
+    #[test]
+    fn clicking_the_bytes() {
+        assert_eq!(nibble_at_column(0), 0);
+        assert_eq!(nibble_at_column(FIRST_BYTE_COLUMN + 1), 1);
+        // The space after a byte belongs to its low nibble.
+        assert_eq!(nibble_at_column(FIRST_BYTE_COLUMN + 2), 1);
+        assert_eq!(nibble_at_column(nibble_column(16)), 16);
+        assert_eq!(nibble_at_column(BAR_COLUMN), BYTES_PER_ROW * 2 - 1);
+    }
+}
@@ -1,5 +1,3 @@
-use termion::color;
-
 #[derive(PartialEq, Clone, Copy)]
 pub enum Type {
     None,
@@ -10,19 +8,62 @@ pub enum Type {
     Comment,
     PrimaryKeywords,
     SecondaryKeywords,
+    Escape,
+    Lifetime,
+    Attribute,
+    Preprocessor,
+    Function,
+    Typename,
+    Field,
+    Macro,
+}
+
+// The state a row ends in, which the next row starts from. A comment or a string that isn't
+// closed on its row goes on over the following rows. The delimiters are found by their index in
+// the lists of the filetype.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum State {
+    #[default]
+    Normal,
+    // In a block comment, where nested comments count their depth.
+    Comment {
+        pair: usize,
+        depth: usize,
+    },
+    // In a string, where '\' escapes the next character.
+    String {
+        pair: usize,
+    },
+    // In a string without escapes.
+    RawString {
+        pair: usize,
+    },
+    // In a string such as r#"..."#, which is closed by '"' and the same number of hashes.
+    HashString {
+        hashes: usize,
+    },
 }
 
 impl Type {
-    pub fn to_color(self) -> impl color::Color {
+    // Return the scope of the theme the kind of text is drawn with.
+    pub fn scope(self) -> &'static str {
         match self {
-            Type::Number => color::Rgb(220, 163, 163),
-            Type::Match => color::Rgb(38, 139, 210),
-            Type::String => color::Rgb(211, 54, 130),
-            Type::Character => color::Rgb(108, 113, 196),
-            Type::Comment => color::Rgb(133, 153, 0),
-            Type::PrimaryKeywords => color::Rgb(170, 150, 218),
-            Type::SecondaryKeywords => color::Rgb(149, 196, 216),
-            _ => color::Rgb(255, 255, 255),
+            Type::None => "text",
+            Type::Number => "number",
+            Type::Match => "search",
+            Type::String => "string",
+            Type::Character => "character",
+            Type::Comment => "comment",
+            Type::PrimaryKeywords => "keyword",
+            Type::SecondaryKeywords => "keyword.secondary",
+            Type::Escape => "string.escape",
+            Type::Lifetime => "lifetime",
+            Type::Attribute => "attribute",
+            Type::Preprocessor => "preprocessor",
+            Type::Function => "function",
+            Type::Typename => "type",
+            Type::Field => "field",
+            Type::Macro => "macro",
         }
     }
 }
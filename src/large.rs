@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Write};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Files larger than this are opened in the large file mode, the size can be changed with
+// ':set largefile=<megabytes>'.
+static THRESHOLD: AtomicU64 = AtomicU64::new(64 * 1024 * 1024);
+
+// How many bytes are read at a time when indexing the lines or searching. A line longer than this
+// is cut, since the rows of a large file are only shown.
+const INDEX_CHUNK: usize = 1024 * 1024;
+
+pub fn set_threshold(megabytes: u64) {
+    THRESHOLD.store(megabytes.saturating_mul(1024 * 1024), Ordering::Relaxed);
+}
+
+// Return true if a file of the given size should be opened in the large file mode.
+pub fn is_large(len: u64) -> bool {
+    len > THRESHOLD.load(Ordering::Relaxed)
+}
+
+// A file that is too large to be read into rows up front. The bytes are read from the file as
+// they are needed, a window at a time, and its lines are indexed only as far as they have been
+// needed.
+pub struct LargeFile {
+    file: File,
+    len: usize,         // The size of the file when it was opened or last extended
+    starts: Vec<usize>, // The offsets where the lines found so far start
+    indexed: usize,     // How far the file has been scanned for line breaks
+}
+
+impl LargeFile {
+    pub fn open(file_name: &str) -> Result<Self, Error> {
+        let file = File::open(file_name)?;
+        let len = file.metadata()?.len() as usize;
+        let starts = if len == 0 { Vec::new() } else { vec![0] };
+
+        Ok(Self {
+            file,
+            len,
+            starts,
+            indexed: 0,
+        })
+    }
+
+    // Open the file again after more bytes have been added to its end. The lines found so far
+    // stay valid, so only the new bytes have to be indexed.
+    pub fn extend(&mut self, file_name: &str) -> Result<(), Error> {
+        let file = File::open(file_name)?;
+        let len = file.metadata()?.len() as usize;
+        // A line break at the old end was indexed without a line starting after it.
+        let skipped = self.len == 0 || self.ends_with_newline();
+        if len > self.len && skipped && self.is_indexed() {
+            self.starts.push(self.len);
+        }
+        self.file = file;
+        self.len = len;
+        Ok(())
+    }
+
+    // Read the bytes in the given range. A program may make the file shorter at any time, and
+    // then fewer bytes are returned until the file check notices the change and reloads it.
+    fn read(&self, range: Range<usize>) -> Vec<u8> {
+        let mut buffer = vec![0; range.len()];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self
+                .file
+                .read_at(&mut buffer[filled..], (range.start + filled) as u64)
+            {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        buffer.truncate(filled);
+        buffer
+    }
+
+    // Write the whole file into the writer a window at a time.
+    pub fn copy_to(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        let mut offset = 0;
+        while offset < self.len {
+            let end = offset.saturating_add(INDEX_CHUNK).min(self.len);
+            let bytes = self.read(offset..end);
+            if bytes.len() < end - offset {
+                return Err(Error::other("the file got shorter while it was read"));
+            }
+            writer.write_all(&bytes)?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    pub fn ends_with_newline(&self) -> bool {
+        self.len > 0 && self.read(self.len - 1..self.len) == b"\n"
+    }
+
+    // Return true if the first line ends with a carriage return and a line feed.
+    pub fn is_dos(&mut self) -> bool {
+        self.index_to(0);
+        match self.starts.get(1) {
+            Some(next) if *next >= 2 => self.read(next - 2..next - 1) == b"\r",
+            _ => false,
+        }
+    }
+
+    // Return true once every line of the file has been found.
+    pub fn is_indexed(&self) -> bool {
+        self.indexed == self.len
+    }
+
+    // Return the number of lines found so far.
+    pub fn lines(&self) -> usize {
+        self.starts.len()
+    }
+
+    // Scan the file until the given line and the one after it have been found, or until the end
+    // of the file.
+    pub fn index_to(&mut self, line: usize) {
+        while self.starts.len() <= line.saturating_add(1) && !self.is_indexed() {
+            self.index_chunk();
+        }
+    }
+
+    // Scan the file until the given offset.
+    fn index_to_offset(&mut self, offset: usize) {
+        while self.indexed <= offset && !self.is_indexed() {
+            self.index_chunk();
+        }
+    }
+
+    fn index_chunk(&mut self) {
+        let end = self.indexed.saturating_add(INDEX_CHUNK).min(self.len);
+        for (position, byte) in self.read(self.indexed..end).iter().enumerate() {
+            let next = self.indexed + position + 1;
+            if *byte == b'\n' && next < self.len {
+                self.starts.push(next);
+            }
+        }
+        self.indexed = end;
+    }
+
+    // Return the line with the given index without its line break.
+    pub fn line(&mut self, index: usize) -> Option<String> {
+        self.index_to(index);
+        let start = *self.starts.get(index)?;
+        let end = match self.starts.get(index + 1) {
+            Some(next) => next - 1,
+            None if self.ends_with_newline() => self.len - 1,
+            None => self.len,
+        };
+
+        let line = self.read(start..end.min(start.saturating_add(INDEX_CHUNK)));
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+
+    // Return the index of the line that contains the given offset.
+    fn line_at(&mut self, offset: usize) -> usize {
+        self.index_to_offset(offset);
+        self.starts
+            .partition_point(|start| *start <= offset)
+            .saturating_sub(1)
+    }
+
+    // Find the next line after the given line that contains the query. The windows overlap by
+    // the length of the query, so that a match across their border is found too.
+    pub fn find_after(&mut self, query: &str, line: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        self.index_to(line);
+        let mut start = *self.starts.get(line + 1)?;
+        let overlap = query.len() - 1;
+        while start < self.len {
+            let end = start.saturating_add(INDEX_CHUNK + overlap).min(self.len);
+            let window = self.read(start..end);
+            if let Some(position) = window
+                .windows(query.len())
+                .position(|window| window == query.as_bytes())
+            {
+                return Some(self.line_at(start + position));
+            }
+            if end == self.len || window.len() < end - start {
+                return None;
+            }
+            start += INDEX_CHUNK;
+        }
+        None
+    }
+
+    // Find the closest line before the given line that contains the query.
+    pub fn find_before(&mut self, query: &str, line: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        self.index_to(line);
+        let mut end = *self.starts.get(line)?;
+        let overlap = query.len() - 1;
+        loop {
+            let start = end.saturating_sub(INDEX_CHUNK + overlap);
+            let window = self.read(start..end);
+            if let Some(position) = window
+                .windows(query.len())
+                .rposition(|window| window == query.as_bytes())
+            {
+                return Some(self.line_at(start + position));
+            }
+            if start == 0 {
+                return None;
+            }
+            end = start + overlap;
+        }
+    }
+}
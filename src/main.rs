@@ -1,20 +1,74 @@
+mod args;
+mod color_depth;
 mod document;
 mod editor;
+mod encoding;
+mod event;
 mod filetype;
+mod hex;
 mod highlighting;
+mod large;
 mod row;
+mod swap;
+#[cfg(feature = "tree-sitter")]
+mod syntax_tree;
 mod terminal;
+mod textmate;
+mod theme;
+
+use args::Action;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 
 pub use document::Document;
+pub use document::LineEnding;
 use editor::Editor;
 pub use editor::Position;
 pub use editor::SearchDirection;
+pub use event::Event;
+pub use event::EventLoop;
+pub use event::JobOutput;
+pub use event::Signal;
+pub use event::Timer;
 pub use filetype::FileType;
 pub use filetype::HighlightOptions;
 pub use row::Row;
 pub use terminal::Terminal;
+pub use theme::Style;
+pub use theme::Theme;
 
 fn main() {
-    // Run the editor instance
-    Editor::default().run();
+    // Run the editor instance. If the editor panics, the edited documents are written into swap
+    // files before the panic continues, so that the work can be recovered.
+    let options = match args::parse(env::args()) {
+        Ok(Action::Run(options)) => options,
+        Ok(Action::Print(text)) => {
+            println!("{}", text);
+            return;
+        }
+        Err(error) => {
+            eprintln!("see: {}", error);
+            eprintln!("try 'see --help' for more information");
+            process::exit(2);
+        }
+    };
+
+    let mut editor = Editor::new(options);
+    let result = match panic::catch_unwind(AssertUnwindSafe(|| editor.run())) {
+        Ok(result) => result,
+        Err(panic) => {
+            editor.write_swap_files();
+            drop(editor);
+            panic::resume_unwind(panic);
+        }
+    };
+
+    // The editor is dropped before the error is printed, so that the terminal has already been
+    // restored.
+    drop(editor);
+    if let Err(error) = result {
+        eprintln!("see: {}", error);
+        process::exit(1);
+    }
 }
@@ -1,8 +1,9 @@
-use crate::highlighting;
+use crate::highlighting::{self, State};
 use crate::HighlightOptions;
 use crate::SearchDirection;
+use crate::Theme;
 use std::cmp;
-use termion::color;
+use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Default)]
@@ -10,16 +11,28 @@ pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
     len: usize,
+    start_state: Option<State>, // The state the row was highlighted from, if it has been
+    end_state: State,           // The state the row ends in, which the next row starts from
+    matches: Vec<Range<usize>>, // The matches of the searched word, drawn over the highlighting
 }
 
 impl Row {
     // The render method formats a row struct into string format, such that the string can be
     // easily formatted on a terminal line.
-    pub fn render(&self, start: usize, end: usize) -> String {
+    // The row is drawn in the styles of the theme, and the selected part of the row is drawn in
+    // the selection style over them. The row ends in the style of the plain text.
+    pub fn render(
+        &self,
+        start: usize,
+        end: usize,
+        selection: Option<Range<usize>>,
+        theme: &Theme,
+    ) -> String {
         let end = cmp::min(end, self.string.len());
         let start = cmp::min(start, end);
         let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
+        let text = theme.text();
+        let mut current_style = text;
         for (index, grapheme) in self.string[..]
             .graphemes(true)
             .enumerate()
@@ -27,26 +40,36 @@ impl Row {
             .take(end - start)
         {
             if let Some(c) = grapheme.chars().next() {
-                let highlighting_type = self
-                    .highlighting
-                    .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-
-                if current_highlighting != highlighting_type {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&start_highlight[..]);
+                let highlighting_type = if self.matches.iter().any(|range| range.contains(&index)) {
+                    highlighting::Type::Match
+                } else {
+                    self.highlighting
+                        .get(index)
+                        .copied()
+                        .unwrap_or(highlighting::Type::None)
+                };
+                let mut style = theme.style(highlighting_type.scope()).over(text);
+                if selection
+                    .as_ref()
+                    .is_some_and(|selection| selection.contains(&index))
+                {
+                    style = theme.style("ui.selection").over(style);
+                }
+
+                if current_style != style {
+                    current_style = style;
+                    result.push_str(&style.escape());
                 }
                 if c == '\t' {
-                    result.push_str(" ");
+                    result.push(' ');
                 } else {
                     result.push(c);
                 }
             }
         }
-        let end_hightlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_hightlight[..]);
+        if current_style != text {
+            result.push_str(&text.escape());
+        }
         result
     }
 
@@ -58,16 +81,28 @@ impl Row {
         self.len == 0
     }
 
-    pub fn highlight(&mut self, opts: &HighlightOptions, word: Option<&str>) {
+    // Highlight the row, starting in the state the previous row ended in.
+    pub fn highlight(&mut self, opts: &HighlightOptions, start: State) {
         self.highlighting = Vec::new();
         let chars: Vec<char> = self.string.chars().collect();
         let mut index = 0;
+        let mut state = start;
 
         while let Some(c) = chars.get(index) {
-            if self.highlight_char(&mut index, opts, *c, &chars)
-                || self.highlight_comment(&mut index, opts, *c, &chars)
-                || self.highlight_primary_keywords(&mut index, &opts, &chars)
-                || self.highlight_secondary_keywords(&mut index, &opts, &chars)
+            if state != State::Normal {
+                state = self.highlight_block(&mut index, opts, &chars, state);
+                continue;
+            }
+            if let Some(opened) = self.open_block(&mut index, opts, &chars) {
+                state = opened;
+                continue;
+            }
+            if self.highlight_preprocessor(&mut index, opts, &chars)
+                || self.highlight_attribute(&mut index, opts, &chars)
+                || self.highlight_char(&mut index, opts, *c, &chars)
+                || self.highlight_comment(&mut index, opts, &chars)
+                || self.highlight_primary_keywords(&mut index, opts, &chars)
+                || self.highlight_secondary_keywords(&mut index, opts, &chars)
                 || self.highlight_string(&mut index, opts, *c, &chars)
                 || self.highlight_number(&mut index, opts, *c, &chars)
             {
@@ -78,31 +113,178 @@ impl Row {
             index += 1;
         }
 
-        self.highlight_match(word);
+        self.start_state = Some(start);
+        self.end_state = state;
     }
 
-    fn highlight_match(&mut self, word: Option<&str>) {
-        if let Some(word) = word {
-            if word.is_empty() {
-                return;
-            }
+    // Highlight the row from spans of bytes, where a later span is drawn over an earlier one.
+    pub fn highlight_spans(&mut self, spans: &[(Range<usize>, highlighting::Type)]) {
+        let len = self.string.len();
+        let mut kinds = vec![highlighting::Type::None; len];
+        for (range, kind) in spans {
+            let end = cmp::min(range.end, len);
+            let start = cmp::min(range.start, end);
+            kinds[start..end].fill(*kind);
+        }
+        self.highlighting = self
+            .string
+            .grapheme_indices(true)
+            .map(|(index, _)| kinds[index])
+            .collect();
+        // The row is highlighted by its keywords again if the filetype changes.
+        self.start_state = None;
+    }
 
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    for i in index.saturating_add(search_match)..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
-                    }
+    // Return true if the row has been highlighted starting from the given state, so that its
+    // highlighting is still right.
+    pub fn is_highlighted_from(&self, start: State) -> bool {
+        self.start_state == Some(start)
+    }
 
-                    index = next_index;
-                } else {
-                    break;
+    pub fn reset_highlighting(&mut self) {
+        self.start_state = None;
+    }
+
+    pub fn end_state(&self) -> State {
+        self.end_state
+    }
+
+    fn push_highlighting(&mut self, index: &mut usize, kind: highlighting::Type, count: usize) {
+        for _ in 0..count {
+            self.highlighting.push(kind);
+            *index += 1;
+        }
+    }
+
+    // Start a block comment or a string that may go on over the following rows. The delimiter
+    // is highlighted, and the state inside the block is returned.
+    fn open_block(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightOptions,
+        chars: &[char],
+    ) -> Option<State> {
+        let rest = &chars[*index..];
+        let find = |pairs: &[(String, String)]| {
+            pairs
+                .iter()
+                .position(|(open, _)| starts_with(rest, open))
+                .map(|pair| (pair, pairs[pair].0.chars().count()))
+        };
+
+        let comment = find(opts.block_comments()).filter(|_| opts.comments());
+        if let Some((pair, length)) = comment {
+            self.push_highlighting(index, highlighting::Type::Comment, length);
+            return Some(State::Comment { pair, depth: 1 });
+        }
+        if !opts.strings() {
+            return None;
+        }
+
+        let (state, length) = if let Some((pair, length)) = find(opts.multiline_strings()) {
+            (State::String { pair }, length)
+        } else if let Some((pair, length)) = find(opts.raw_strings()) {
+            (State::RawString { pair }, length)
+        } else if opts.hash_raw_strings() {
+            let (hashes, length) = hash_string_start(chars, *index)?;
+            (State::HashString { hashes }, length)
+        } else {
+            return None;
+        };
+        self.push_highlighting(index, highlighting::Type::String, length);
+        Some(state)
+    }
+
+    // Go on with a block comment or a string, a character or a delimiter at a time. The state
+    // after it is returned, which is the normal state once the block has been closed.
+    fn highlight_block(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightOptions,
+        chars: &[char],
+        state: State,
+    ) -> State {
+        let rest = &chars[*index..];
+        match state {
+            State::Normal => State::Normal,
+            State::Comment { pair, depth } => {
+                let kind = highlighting::Type::Comment;
+                let (open, close) = match opts.block_comments().get(pair) {
+                    Some(delimiters) => delimiters,
+                    None => return State::Normal,
+                };
+                if opts.nested_comments() && starts_with(rest, open) {
+                    self.push_highlighting(index, kind, open.chars().count());
+                    return State::Comment {
+                        pair,
+                        depth: depth + 1,
+                    };
+                }
+                if starts_with(rest, close) {
+                    self.push_highlighting(index, kind, close.chars().count());
+                    return match depth {
+                        1 => State::Normal,
+                        _ => State::Comment {
+                            pair,
+                            depth: depth - 1,
+                        },
+                    };
+                }
+                self.push_highlighting(index, kind, 1);
+                state
+            }
+            State::String { pair } | State::RawString { pair } => {
+                let kind = highlighting::Type::String;
+                let pairs = match state {
+                    State::String { .. } => opts.multiline_strings(),
+                    _ => opts.raw_strings(),
+                };
+                let close = match pairs.get(pair) {
+                    Some((_, close)) => close,
+                    None => return State::Normal,
+                };
+                if matches!(state, State::String { .. }) && rest[0] == '\\' {
+                    self.push_highlighting(index, highlighting::Type::Escape, escape_length(rest));
+                    return state;
+                }
+                if starts_with(rest, close) {
+                    self.push_highlighting(index, kind, close.chars().count());
+                    return State::Normal;
+                }
+                self.push_highlighting(index, kind, 1);
+                state
+            }
+            State::HashString { hashes } => {
+                let kind = highlighting::Type::String;
+                let closed = rest[0] == '"'
+                    && rest.len() > hashes
+                    && rest[1..=hashes].iter().all(|c| *c == '#');
+                if closed {
+                    self.push_highlighting(index, kind, hashes + 1);
+                    return State::Normal;
                 }
+                self.push_highlighting(index, kind, 1);
+                state
             }
         }
     }
 
+    // Find the matches of the searched word, which are drawn over the highlighting.
+    pub fn highlight_match(&mut self, word: Option<&str>) {
+        self.matches.clear();
+        let word = match word {
+            Some(word) if !word.is_empty() => word,
+            _ => return,
+        };
+
+        let length = word.graphemes(true).count();
+        let mut index = 0;
+        while let Some(found) = self.find(word, index, SearchDirection::Forward) {
+            self.matches.push(found..found + length);
+            index = found + length;
+        }
+    }
+
     fn highlight_str(
         &mut self,
         index: &mut usize,
@@ -154,7 +336,7 @@ impl Row {
                 }
             }
 
-            if self.highlight_str(index, &word, chars, hl_type) {
+            if self.highlight_str(index, word, chars, hl_type) {
                 return true;
             }
         }
@@ -190,6 +372,8 @@ impl Row {
         )
     }
 
+    // Highlight a character literal such as 'a' or '\n', or a lifetime such as 'a, which has no
+    // closing quote.
     fn highlight_char(
         &mut self,
         index: &mut usize,
@@ -197,114 +381,211 @@ impl Row {
         c: char,
         chars: &[char],
     ) -> bool {
-        if opts.characters() && c == '\'' {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                let closing_index = if *next_char == '\\' {
-                    index.saturating_add(3)
-                } else {
-                    index.saturating_add(2)
-                };
+        if !opts.characters() || c != '\'' {
+            return false;
+        }
 
-                if let Some(closing_char) = chars.get(closing_index) {
-                    if *closing_char == '\'' {
-                        for _ in 0..=closing_index.saturating_sub(*index) {
-                            self.highlighting.push(highlighting::Type::Character);
-                            *index += 1;
-                        }
+        let rest = &chars[*index..];
+        let escaped = rest.get(1) == Some(&'\\');
+        let length = match rest.get(1) {
+            Some('\\') => 1 + escape_length(&rest[1..]),
+            Some(_) => 2,
+            None => return false,
+        };
+        if rest.get(length) == Some(&'\'') {
+            let kind = if escaped {
+                highlighting::Type::Escape
+            } else {
+                highlighting::Type::Character
+            };
+            self.push_highlighting(index, highlighting::Type::Character, 1);
+            self.push_highlighting(index, kind, length - 1);
+            self.push_highlighting(index, highlighting::Type::Character, 1);
+            return true;
+        }
 
-                        return true;
-                    }
-                }
-            }
+        if opts.lifetimes() && (rest[1].is_alphabetic() || rest[1] == '_') {
+            let name = rest[1..]
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_')
+                .count();
+            self.push_highlighting(index, highlighting::Type::Lifetime, 1 + name);
+            return true;
         }
 
         false
     }
 
-    fn highlight_comment(
+    // Highlight an attribute such as #[derive(Clone)], which goes on to its closing bracket, or a
+    // decorator such as @property, which is a name after the prefix.
+    fn highlight_attribute(
         &mut self,
         index: &mut usize,
         opts: &HighlightOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index += 1;
-                    }
-
-                    return true;
+        let rest = &chars[*index..];
+        let prefix = match opts
+            .attributes()
+            .iter()
+            .find(|prefix| starts_with(rest, prefix))
+        {
+            Some(prefix) => prefix,
+            None => return false,
+        };
+        let prefix_length = prefix.chars().count();
+
+        let length = if prefix.ends_with('[') {
+            let mut depth = 0;
+            let mut end = rest.len();
+            for (position, c) in rest.iter().enumerate() {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => continue,
                 }
-            };
+                if depth == 0 {
+                    end = position + 1;
+                    break;
+                }
+            }
+            end
+        } else {
+            // An '@' between two words is an operator rather than a decorator.
+            if *index > 0 && !chars[*index - 1].is_whitespace() {
+                return false;
+            }
+            let name = rest[prefix_length..]
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+                .count();
+            if name == 0 {
+                return false;
+            }
+            prefix_length + name
+        };
+
+        self.push_highlighting(index, highlighting::Type::Attribute, length);
+        true
+    }
+
+    // Highlight a preprocessor directive such as #define at the start of a row. The file of an
+    // #include is highlighted as a string also when it is in angle brackets.
+    fn highlight_preprocessor(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightOptions,
+        chars: &[char],
+    ) -> bool {
+        // This is checked at every character, so the characters before are only looked at once a
+        // '#' has been found.
+        if !opts.preprocessor() || chars[*index] != '#' {
+            return false;
+        }
+        if !chars[..*index].iter().all(|c| c.is_whitespace()) {
+            return false;
         }
 
-        false
+        let spaces = |from: usize| {
+            chars[from..]
+                .iter()
+                .take_while(|c| c.is_whitespace())
+                .count()
+        };
+        let name_start = *index + 1 + spaces(*index + 1);
+        let name: String = chars[name_start..]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '_')
+            .collect();
+        let length = name_start - *index + name.chars().count();
+        self.push_highlighting(index, highlighting::Type::Preprocessor, length);
+
+        if name == "include" || name == "import" {
+            self.push_highlighting(index, highlighting::Type::None, spaces(*index));
+            if chars.get(*index) == Some(&'<') {
+                let length = chars[*index..]
+                    .iter()
+                    .position(|c| *c == '>')
+                    .map_or(chars.len() - *index, |end| end + 1);
+                self.push_highlighting(index, highlighting::Type::String, length);
+            }
+        }
+        true
     }
 
-    fn highlight_string(
+    fn highlight_comment(
         &mut self,
         index: &mut usize,
         opts: &HighlightOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.strings() && c == '"' {
-            loop {
-                self.highlighting.push(highlighting::Type::String);
+        if !opts.comments() {
+            return false;
+        }
+        let starts_comment = opts
+            .line_comments()
+            .iter()
+            .any(|token| starts_with(&chars[*index..], token));
+        if starts_comment {
+            for _ in *index..chars.len() {
+                self.highlighting.push(highlighting::Type::Comment);
                 *index += 1;
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char == '"' {
-                        break;
-                    }
-                } else {
-                    break;
-                }
             }
 
-            self.highlighting.push(highlighting::Type::String);
-            *index += 1;
             return true;
         }
 
         false
     }
 
-    fn highlight_number(
+    fn highlight_string(
         &mut self,
         index: &mut usize,
         opts: &HighlightOptions,
         c: char,
         chars: &[char],
     ) -> bool {
-        if opts.numbers() && c.is_ascii_digit() {
-            if *index > 0 {
-                let prev_char = chars[*index - 1];
-                if !is_separator(prev_char) {
-                    return false;
-                }
-            }
+        if !opts.strings() || !opts.string_delimiters().contains(&c) {
+            return false;
+        }
 
-            loop {
-                self.highlighting.push(highlighting::Type::Number);
-                *index += 1;
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char != '.' && !next_char.is_ascii_digit() {
-                        break;
-                    }
-                } else {
-                    break;
-                }
+        self.push_highlighting(index, highlighting::Type::String, 1);
+        while let Some(next_char) = chars.get(*index) {
+            if *next_char == '\\' {
+                let length = escape_length(&chars[*index..]);
+                self.push_highlighting(index, highlighting::Type::Escape, length);
+                continue;
+            }
+            self.push_highlighting(index, highlighting::Type::String, 1);
+            if *next_char == c {
+                break;
             }
-            return true;
         }
+        true
+    }
 
-        false
+    fn highlight_number(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightOptions,
+        c: char,
+        chars: &[char],
+    ) -> bool {
+        if !opts.numbers() || !c.is_ascii_digit() {
+            return false;
+        }
+        if *index > 0 && !is_separator(chars[*index - 1]) {
+            return false;
+        }
+
+        let length = number_length(&chars[*index..]);
+        self.push_highlighting(index, highlighting::Type::Number, length);
+        true
     }
 
+    // The changes to a row leave its highlighting to be done again.
     pub fn insert(&mut self, at: usize, c: char) {
+        self.start_state = None;
         if at >= self.len() {
             self.string.push(c);
             self.len += 1;
@@ -328,6 +609,7 @@ impl Row {
         if at >= self.len() {
             return;
         }
+        self.start_state = None;
         let mut result: String = String::new();
         let mut length = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
@@ -343,6 +625,7 @@ impl Row {
     pub fn append(&mut self, new: &Self) {
         self.string = format!("{}{}", self.string, new.string);
         self.len += new.len;
+        self.start_state = None;
     }
 
     pub fn split(&mut self, at: usize) -> Self {
@@ -362,10 +645,11 @@ impl Row {
 
         self.string = row;
         self.len = length;
+        self.start_state = None;
         Self {
             string: splitted_row,
             len: splitted_length,
-            highlighting: Vec::new(),
+            ..Self::default()
         }
     }
 
@@ -420,7 +704,7 @@ impl From<&str> for Row {
         Self {
             string: String::from(s),
             len: s.graphemes(true).count(),
-            highlighting: Vec::new(),
+            ..Self::default()
         }
     }
 }
@@ -428,3 +712,143 @@ impl From<&str> for Row {
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
+
+// Return true if the characters start with the given token, which can't be empty.
+fn starts_with(chars: &[char], token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .enumerate()
+            .all(|(index, c)| chars.get(index) == Some(&c))
+}
+
+// Find a raw string such as r"...", r#"..."# or br"..." starting at the index, and return the
+// number of hashes in it and the length of the part before the text.
+fn hash_string_start(chars: &[char], index: usize) -> Option<(usize, usize)> {
+    if index > 0 && !is_separator(chars[index - 1]) {
+        return None;
+    }
+    let mut end = index;
+    if chars.get(end) == Some(&'b') {
+        end += 1;
+    }
+    if chars.get(end) != Some(&'r') {
+        return None;
+    }
+    end += 1;
+    let hashes = chars[end..].iter().take_while(|c| **c == '#').count();
+    end += hashes;
+    (chars.get(end) == Some(&'"')).then_some((hashes, end + 1 - index))
+}
+
+// Return the length of the escape sequence that starts with the backslash at the start of the
+// characters, such as \n, \x41, \u{1F600} or \101.
+fn escape_length(chars: &[char]) -> usize {
+    let hex_digits = |count: usize| {
+        chars[2..]
+            .iter()
+            .take(count)
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count()
+    };
+    match chars.get(1) {
+        None => 1,
+        Some('u') if chars.get(2) == Some(&'{') => chars
+            .iter()
+            .position(|c| *c == '}')
+            .map_or(chars.len(), |end| end + 1),
+        Some('x') => 2 + hex_digits(2),
+        Some('u') => 2 + hex_digits(4),
+        Some('U') => 2 + hex_digits(8),
+        Some('0'..='7') => {
+            1 + chars[1..]
+                .iter()
+                .take(3)
+                .take_while(|c| ('0'..='7').contains(*c))
+                .count()
+        }
+        Some(_) => 2,
+    }
+}
+
+// Return the length of the number at the start of the characters: a decimal number with an
+// optional fraction and exponent, or a hexadecimal, octal or binary number. Underscores can
+// separate the digits, and a suffix such as "u32", "f" or "L" is a part of the number.
+fn number_length(chars: &[char]) -> usize {
+    let digits = |from: usize, radix: u32| {
+        from + chars[from..]
+            .iter()
+            .take_while(|c| c.is_digit(radix) || **c == '_')
+            .count()
+    };
+    let is_digit = |index: usize| chars.get(index).is_some_and(char::is_ascii_digit);
+
+    let prefix = chars.get(1).map(char::to_ascii_lowercase);
+    let end = match (chars[0], prefix) {
+        ('0', Some('x')) => digits(2, 16),
+        ('0', Some('o')) => digits(2, 8),
+        ('0', Some('b')) => digits(2, 2),
+        _ => {
+            let mut end = digits(0, 10);
+            // A dot without a digit after it is a method call or a range, as in 1..2.
+            if chars.get(end) == Some(&'.') && is_digit(end + 1) {
+                end = digits(end + 1, 10);
+            }
+            if matches!(chars.get(end), Some('e' | 'E')) {
+                let sign = usize::from(matches!(chars.get(end + 1), Some('+' | '-')));
+                if is_digit(end + 1 + sign) {
+                    end = digits(end + 1 + sign, 10);
+                }
+            }
+            end
+        }
+    };
+    end + chars[end..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escape(text: &str) -> usize {
+        escape_length(&text.chars().collect::<Vec<char>>())
+    }
+
+    fn number(text: &str) -> usize {
+        number_length(&text.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("\\n rest"), 2);
+        assert_eq!(escape("\\"), 1);
+        assert_eq!(escape("\\x41z"), 4);
+        assert_eq!(escape("\\x4"), 3);
+        assert_eq!(escape("\\u00e9!"), 6);
+        assert_eq!(escape("\\U0001F600"), 10);
+        assert_eq!(escape("\\u{1F600}x"), 9);
+        // An unclosed brace runs to the end.
+        assert_eq!(escape("\\u{1F6"), 6);
+        assert_eq!(escape("\\1018"), 4);
+        assert_eq!(escape("\\0"), 2);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number("42;"), 2);
+        assert_eq!(number("1_000_000 "), 9);
+        assert_eq!(number("3.14)"), 4);
+        assert_eq!(number("1..2"), 1);
+        assert_eq!(number("1.max(2)"), 1);
+        assert_eq!(number("6.02e+23,"), 8);
+        assert_eq!(number("1e"), 2);
+        assert_eq!(number("0xFF_u8 "), 7);
+        assert_eq!(number("0o17"), 4);
+        assert_eq!(number("0B101"), 5);
+        assert_eq!(number("10usize"), 7);
+        assert_eq!(number("2.5f "), 4);
+    }
+}
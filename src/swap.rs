@@ -0,0 +1,104 @@
+use crate::Document;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Error, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+// Return the directory in which the swap files are kept.
+fn swap_directory() -> Option<PathBuf> {
+    let state_directory = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+
+    Some(state_directory.join("see").join("swap"))
+}
+
+// Return the path of the swap file for a given file. The whole path of the file is encoded into the
+// name, so that files with the same name in different directories don't share a swap file. The
+// slashes and the percent signs are escaped the way URLs escape them, so no two paths get the same
+// name.
+fn swap_path(file_name: &str) -> Option<PathBuf> {
+    if file_name.is_empty() {
+        return None;
+    }
+
+    let path = Path::new(file_name);
+    let absolute = match path.canonicalize() {
+        Ok(absolute) => absolute,
+        Err(_) => env::current_dir().ok()?.join(path),
+    };
+    let name = absolute
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+
+    Some(swap_directory()?.join(format!("{}.swp", name)))
+}
+
+// Write the contents of a document into its swap file. Documents without a name don't have a swap
+// file, and the hex view writes the bytes it shows. Only the user can read the swap file, since
+// the file it is for may be private.
+pub fn write(document: &Document) -> Result<(), Error> {
+    let path = match document.file_name.as_deref().and_then(swap_path) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode is only used for a new file, a swap file left by an older version keeps its own.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(&document.to_bytes())
+}
+
+// Remove the swap file of a document, for example after it has been saved.
+pub fn remove(document: &Document) {
+    if let Some(path) = document.file_name.as_deref().and_then(swap_path) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Return the contents of a swap file, if the swap file is newer than the file itself.
+pub fn recoverable(file_name: &str) -> Option<Vec<u8>> {
+    let path = swap_path(file_name)?;
+    let swap_modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+
+    // A swap file without the file means that the file was never saved.
+    if let Ok(file_modified) = fs::metadata(file_name).and_then(|meta| meta.modified()) {
+        if file_modified >= swap_modified {
+            return None;
+        }
+    }
+
+    fs::read(path).ok()
+}
+
+// Count the lines that were added and removed between two versions of a text. The order of the
+// lines is ignored, this is only used to give an idea of how much the versions differ.
+pub fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in old.lines() {
+        *counts.entry(line).or_insert(0) -= 1;
+    }
+    for line in new.lines() {
+        *counts.entry(line).or_insert(0) += 1;
+    }
+
+    let added = counts.values().filter(|c| **c > 0).sum::<isize>() as usize;
+    let removed = counts
+        .values()
+        .filter(|c| **c < 0)
+        .map(|c| -c)
+        .sum::<isize>() as usize;
+    (added, removed)
+}
@@ -0,0 +1,245 @@
+use crate::highlighting::Type;
+use crate::Row;
+use std::cmp;
+use std::ops::Range;
+use std::sync::OnceLock;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{InputEdit, Language, Parser, Point, Query, QueryCursor, Tree};
+
+// A grammar compiled into the editor, used for the filetype of the same name. The query that
+// finds what to highlight is compiled the first time a file of the filetype is opened.
+struct Grammar {
+    file_type: &'static str,
+    language: fn() -> Language,
+    // The highlight queries, where the patterns of an earlier query take precedence.
+    queries: &'static [&'static str],
+    highlights: OnceLock<Option<Highlights>>,
+}
+
+static GRAMMARS: [Grammar; 5] = [
+    Grammar {
+        file_type: "rust",
+        language: || tree_sitter_rust::LANGUAGE.into(),
+        queries: &[tree_sitter_rust::HIGHLIGHTS_QUERY],
+        highlights: OnceLock::new(),
+    },
+    Grammar {
+        file_type: "golang",
+        language: || tree_sitter_go::LANGUAGE.into(),
+        queries: &[tree_sitter_go::HIGHLIGHTS_QUERY],
+        highlights: OnceLock::new(),
+    },
+    Grammar {
+        file_type: "python3",
+        language: || tree_sitter_python::LANGUAGE.into(),
+        queries: &[tree_sitter_python::HIGHLIGHTS_QUERY],
+        highlights: OnceLock::new(),
+    },
+    Grammar {
+        file_type: "c",
+        language: || tree_sitter_c::LANGUAGE.into(),
+        queries: &[tree_sitter_c::HIGHLIGHT_QUERY],
+        highlights: OnceLock::new(),
+    },
+    // The C++ query only has what C++ adds to C, so the C query is used after it.
+    Grammar {
+        file_type: "cpp",
+        language: || tree_sitter_cpp::LANGUAGE.into(),
+        queries: &[
+            tree_sitter_cpp::HIGHLIGHT_QUERY,
+            tree_sitter_c::HIGHLIGHT_QUERY,
+        ],
+        highlights: OnceLock::new(),
+    },
+];
+
+// A compiled highlight query, with the kind of highlighting of each of its captures.
+struct Highlights {
+    query: Query,
+    types: Vec<Type>,
+}
+
+impl Highlights {
+    fn new(grammar: &Grammar) -> Option<Self> {
+        let query = Query::new(&(grammar.language)(), &grammar.queries.join("\n")).ok()?;
+        let types = query
+            .capture_names()
+            .iter()
+            .map(|name| capture_type(name))
+            .collect();
+        Some(Highlights { query, types })
+    }
+}
+
+// The syntax tree of a document, which is parsed again after the rows have changed. Only the
+// part of the text that changed is parsed again, since the old tree is reused.
+pub struct SyntaxTree {
+    parser: Parser,
+    highlights: &'static Highlights,
+    tree: Option<Tree>,
+    text: Vec<u8>,                     // The text the tree was parsed from
+    line_starts: Vec<usize>,           // The byte each row starts at in the text
+    changed: bool,                     // Whether the rows have changed since the parse
+    highlighted: Option<Range<usize>>, // The rows highlighted from the current tree
+}
+
+impl SyntaxTree {
+    // Create the syntax tree for a document of the given filetype, if the filetype has a grammar.
+    // Other filetypes are highlighted by their keywords.
+    pub fn new(file_type: &str) -> Option<Self> {
+        let grammar = GRAMMARS
+            .iter()
+            .find(|grammar| grammar.file_type == file_type)?;
+        let highlights = grammar
+            .highlights
+            .get_or_init(|| Highlights::new(grammar))
+            .as_ref()?;
+        let mut parser = Parser::new();
+        parser.set_language(&(grammar.language)()).ok()?;
+
+        Some(SyntaxTree {
+            parser,
+            highlights,
+            tree: None,
+            text: Vec::new(),
+            line_starts: Vec::new(),
+            changed: true,
+            highlighted: None,
+        })
+    }
+
+    // Note that the rows have changed, so that they are parsed again before they are shown.
+    pub fn changed(&mut self) {
+        self.changed = true;
+    }
+
+    // Parse the rows again if they have changed. The old tree is edited to match the new text
+    // first, so that tree-sitter can reuse the parts that didn't change.
+    fn parse(&mut self, rows: &[Row]) {
+        if !self.changed {
+            return;
+        }
+
+        let mut text = Vec::new();
+        let mut line_starts = Vec::with_capacity(rows.len());
+        for row in rows {
+            line_starts.push(text.len());
+            text.extend_from_slice(row.as_bytes());
+            text.push(b'\n');
+        }
+        if let Some(tree) = self.tree.as_mut() {
+            tree.edit(&edit_between(&self.text, &text));
+        }
+
+        self.tree = self.parser.parse(&text, self.tree.as_ref());
+        self.text = text;
+        self.line_starts = line_starts;
+        self.changed = false;
+        self.highlighted = None;
+    }
+
+    // Highlight the given rows from the tree. The rows are highlighted again only if the tree
+    // has been parsed again, or other rows are shown.
+    pub fn highlight_rows(&mut self, rows: &mut [Row], shown: Range<usize>) {
+        self.parse(rows);
+        if shown.is_empty() || self.highlighted.as_ref() == Some(&shown) {
+            return;
+        }
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return,
+        };
+
+        let start = self.line_starts[shown.start];
+        let end = self
+            .line_starts
+            .get(shown.end)
+            .copied()
+            .unwrap_or(self.text.len());
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(start..end);
+        let mut captures = cursor.captures(
+            &self.highlights.query,
+            tree.root_node(),
+            self.text.as_slice(),
+        );
+
+        // The spans of bytes of each row, where a node inside another one is drawn over it. A
+        // node captured by several patterns gets the kind of the first one.
+        let mut spans = vec![Vec::new(); shown.len()];
+        let mut last_node = None;
+        while let Some((found, index)) = captures.next() {
+            let capture = found.captures[*index];
+            if last_node == Some(capture.node.id()) {
+                continue;
+            }
+            last_node = Some(capture.node.id());
+
+            let kind = self.highlights.types[capture.index as usize];
+            let from = capture.node.start_position();
+            let to = capture.node.end_position();
+            for row in cmp::max(from.row, shown.start)..cmp::min(to.row + 1, shown.end) {
+                let start = if row == from.row { from.column } else { 0 };
+                let end = if row == to.row { to.column } else { usize::MAX };
+                spans[row - shown.start].push((start..end, kind));
+            }
+        }
+
+        for (row, spans) in rows[shown.clone()].iter_mut().zip(spans) {
+            row.highlight_spans(&spans);
+        }
+        self.highlighted = Some(shown);
+    }
+}
+
+// Find the kind of highlighting of a capture of a highlight query. The names go from the general
+// to the specific, like 'function.macro'.
+fn capture_type(name: &str) -> Type {
+    let general = name.split('.').next().unwrap_or_default();
+    match (general, name) {
+        (_, "function.macro") => Type::Macro,
+        (_, "constant.builtin") | (_, "variable.builtin") => Type::SecondaryKeywords,
+        ("comment", _) => Type::Comment,
+        ("string", _) => Type::String,
+        ("escape", _) => Type::Escape,
+        ("number", _) => Type::Number,
+        ("keyword", _) => Type::PrimaryKeywords,
+        ("function", _) => Type::Function,
+        ("type", _) | ("constructor", _) => Type::Typename,
+        ("property", _) => Type::Field,
+        ("attribute", _) => Type::Attribute,
+        ("label", _) => Type::Lifetime,
+        _ => Type::None,
+    }
+}
+
+// Find the part of the text that has changed, as an edit that turns the old text into the new.
+fn edit_between(old: &[u8], new: &[u8]) -> InputEdit {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte: old.len() - suffix,
+        new_end_byte: new.len() - suffix,
+        start_position: point_at(new, prefix),
+        old_end_position: point_at(old, old.len() - suffix),
+        new_end_position: point_at(new, new.len() - suffix),
+    }
+}
+
+// Find the row and the column of a byte of the text.
+fn point_at(text: &[u8], byte: usize) -> Point {
+    let before = &text[..byte];
+    let row = before.iter().filter(|c| **c == b'\n').count();
+    let line_start = before
+        .iter()
+        .rposition(|c| *c == b'\n')
+        .map_or(0, |index| index + 1);
+    Point::new(row, byte - line_start)
+}
@@ -1,9 +1,21 @@
 use crate::Position;
+use crate::Style;
+use signal_hook::consts::SIGSTOP;
+use signal_hook::low_level;
 use std::io::{self, stdout, Write};
-use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
+use std::panic;
+use std::sync::OnceLock;
 use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+use termion::style;
+
+// Enables the mouse reporting and bracketed paste, so that pasted text can be told apart from
+// typing.
+const ENABLE_INPUT_MODES: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h\x1b[?2004h";
+const DISABLE_INPUT_MODES: &str = "\x1b[?2004l\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+// The terminal settings from before the editor started, the panic hook restores these.
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
 
 // A way to hold the width and height of the terminal window.
 pub struct Size {
@@ -14,22 +26,75 @@ pub struct Size {
 // The struct that handles all contant with terminal.
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    stdout: RawTerminal<std::io::Stdout>,
 }
 
 impl Terminal {
-    pub fn default() -> Result<Self, std::io::Error> {
+    pub fn new() -> Result<Self, std::io::Error> {
         // Get the terminal's size.
         let size = termion::terminal_size()?;
+
+        // Remember the original settings and restore them before a panic message is printed,
+        // otherwise the message would be lost on the alternate screen.
+        let mut termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut termios) } == 0 {
+            let _ = ORIGINAL_TERMIOS.set(termios);
+        }
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Terminal::leave_screen();
+            if let Some(termios) = ORIGINAL_TERMIOS.get() {
+                unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, termios) };
+            }
+            default_hook(info);
+        }));
+
+        let stdout = stdout().into_raw_mode()?;
+        Terminal::enter_screen();
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2),
             },
-            _stdout: stdout().into_raw_mode()?,
+            stdout,
         })
     }
 
+    // Switch to the alternate screen and enable the input modes the editor uses.
+    fn enter_screen() {
+        print!("{}{}", ToAlternateScreen, ENABLE_INPUT_MODES);
+        let _ = Terminal::flush();
+    }
+
+    // Undo everything enter_screen did and show the cursor again.
+    fn leave_screen() {
+        print!(
+            "{}{}{}{}",
+            style::Reset,
+            DISABLE_INPUT_MODES,
+            termion::cursor::Show,
+            ToMainScreen
+        );
+        let _ = Terminal::flush();
+    }
+
+    // Give the terminal back to the shell and stop the process. This returns once the process has
+    // been continued, at which point the terminal is set up again.
+    pub fn suspend(&mut self) -> Result<(), std::io::Error> {
+        Terminal::leave_screen();
+        self.stdout.suspend_raw_mode()?;
+        low_level::raise(SIGSTOP)?;
+        self.resume()
+    }
+
+    // Set up the terminal again after the process has been continued, the shell might have
+    // changed the terminal settings in the meantime.
+    pub fn resume(&mut self) -> Result<(), std::io::Error> {
+        self.stdout.activate_raw_mode()?;
+        Terminal::enter_screen();
+        self.update_size()
+    }
+
     // Return a terminal's size.
     pub fn size(&self) -> &Size {
         &self.size
@@ -55,13 +120,14 @@ impl Terminal {
         io::stdout().flush()
     }
 
-    // Read a key from standard input.
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
-        }
+    // Update the stored size after the terminal window has been resized.
+    pub fn update_size(&mut self) -> Result<(), std::io::Error> {
+        let size = termion::terminal_size()?;
+        self.size = Size {
+            width: size.0,
+            height: size.1.saturating_sub(2),
+        };
+        Ok(())
     }
 
     // Hide the cursor in the terminal
@@ -79,23 +145,21 @@ impl Terminal {
         print!("{}", termion::clear::CurrentLine);
     }
 
-    // The the background color of the terminal
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    // Draw the text printed after this in the given style.
+    pub fn set_style(style: Style) {
+        print!("{}", style.escape());
     }
 
-    // The foreground color to a given color
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
-    }
-
-    // Reset the background color to the user's terminal's own color.
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    // Go back to the terminal's own colors.
+    pub fn reset_style() {
+        print!("{}", style::Reset);
     }
+}
 
-    // Reset the foreground color to the user's terminal's own text color.
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+// The raw mode is turned off when the stdout field is dropped, this restores the rest of the
+// terminal.
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        Terminal::leave_screen();
     }
 }
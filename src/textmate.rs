@@ -0,0 +1,534 @@
+use crate::filetype;
+use crate::highlighting::Type;
+use crate::Row;
+use plist::{Dictionary, Value};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use syntect::parsing::{
+    ParseState, ScopeStack, SyntaxDefinition, SyntaxReference, SyntaxSet, SyntaxSetBuilder,
+};
+
+// The kinds of highlighting of the scopes of a grammar. A scope gets the kind of the first prefix
+// it starts with, and the innermost scope with a kind decides the kind of the text.
+const SCOPES: [(&str, Type); 28] = [
+    ("comment", Type::Comment),
+    ("constant.character.escape", Type::Escape),
+    ("constant.numeric", Type::Number),
+    ("constant.character", Type::Character),
+    ("constant.language", Type::SecondaryKeywords),
+    ("string", Type::String),
+    ("keyword.operator", Type::None),
+    ("keyword.control.directive", Type::Preprocessor),
+    ("meta.preprocessor", Type::Preprocessor),
+    ("keyword", Type::PrimaryKeywords),
+    ("storage.modifier.lifetime", Type::Lifetime),
+    ("storage.type", Type::SecondaryKeywords),
+    ("storage", Type::PrimaryKeywords),
+    ("support.macro", Type::Macro),
+    ("entity.name.macro", Type::Macro),
+    ("entity.name.function", Type::Function),
+    ("support.function", Type::Function),
+    ("variable.function", Type::Function),
+    ("entity.name.tag", Type::PrimaryKeywords),
+    ("entity.name", Type::Typename),
+    ("entity.other.inherited-class", Type::Typename),
+    ("entity.other.attribute-name", Type::Attribute),
+    ("support.type.property-name", Type::Field),
+    ("support.type", Type::Typename),
+    ("support.class", Type::Typename),
+    ("support.constant", Type::SecondaryKeywords),
+    ("variable.other.member", Type::Field),
+    ("variable.language", Type::SecondaryKeywords),
+];
+
+static GRAMMARS: OnceLock<Grammars> = OnceLock::new();
+
+// The grammars in the user's syntaxes directory, together with the problems found while reading
+// them.
+struct Grammars {
+    set: SyntaxSet,
+    errors: Vec<String>,
+}
+
+// Highlights the rows of a document by a grammar. The parser's state at the start of each row is
+// kept for the rows that have been highlighted, so that a changed row is parsed again from the
+// state the row above it ended in.
+pub struct Highlighter {
+    states: Vec<(ParseState, ScopeStack)>,
+}
+
+impl Highlighter {
+    pub fn new(grammar: &SyntaxReference) -> Self {
+        Self {
+            states: vec![(ParseState::new(grammar), ScopeStack::new())],
+        }
+    }
+
+    // Highlight the given rows. The rows above them must have been highlighted already.
+    pub fn highlight_rows(&mut self, rows: &mut [Row], range: Range<usize>) {
+        let set = &grammars().set;
+        self.states.truncate(range.start + 1);
+        for row in &mut rows[range] {
+            let (mut parser, mut scopes) = match self.states.last() {
+                Some(state) => state.clone(),
+                None => return,
+            };
+
+            // The grammars are read expecting the line break at the end of each line.
+            let line = format!("{}\n", String::from_utf8_lossy(row.as_bytes()));
+            let mut spans = Vec::new();
+            let mut start = 0;
+            for (index, operation) in parser.parse_line(&line, set).unwrap_or_default() {
+                if index > start {
+                    spans.push((start..index, scope_type(&scopes)));
+                    start = index;
+                }
+                if scopes.apply(&operation).is_err() {
+                    break;
+                }
+            }
+            spans.push((start..line.len(), scope_type(&scopes)));
+
+            row.highlight_spans(&spans);
+            self.states.push((parser, scopes));
+        }
+    }
+}
+
+// Find the grammar of a file from its name or its extension, and then from its first line.
+pub fn find(file_name: &str, text: &str) -> Option<&'static SyntaxReference> {
+    let set = &grammars().set;
+    let path = Path::new(file_name);
+    let base_name = path.file_name().and_then(|name| name.to_str());
+    let extension = path.extension().and_then(|extension| extension.to_str());
+
+    base_name
+        .and_then(|name| set.find_syntax_by_extension(name))
+        .or_else(|| extension.and_then(|extension| set.find_syntax_by_extension(extension)))
+        .or_else(|| set.find_syntax_by_first_line(text.lines().next()?))
+}
+
+// Find a grammar by its name or one of its extensions, ignoring case.
+pub fn named(name: &str) -> Option<&'static SyntaxReference> {
+    grammars().set.find_syntax_by_token(name)
+}
+
+// Return the problems found in the grammar files.
+pub fn load_errors() -> &'static [String] {
+    &grammars().errors
+}
+
+fn grammars() -> &'static Grammars {
+    GRAMMARS.get_or_init(load_grammars)
+}
+
+// Find the kind of highlighting of the innermost scope that has one.
+fn scope_type(scopes: &ScopeStack) -> Type {
+    scopes
+        .as_slice()
+        .iter()
+        .rev()
+        .find_map(|scope| {
+            let name = scope.build_string();
+            SCOPES
+                .iter()
+                .find(|(prefix, _)| {
+                    name.strip_prefix(prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+                })
+                .map(|(_, kind)| *kind)
+        })
+        .unwrap_or(Type::None)
+}
+
+// Read the grammars in the user's syntaxes directory in the order of their file names. A missing
+// directory is not an error.
+fn load_grammars() -> Grammars {
+    let mut builder = SyntaxSetBuilder::new();
+    let mut errors = Vec::new();
+
+    let entries = filetype::config_directory()
+        .map(|directory| directory.join("syntaxes"))
+        .and_then(|directory| fs::read_dir(directory).ok());
+    let mut paths: Vec<PathBuf> = entries
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "sublime-syntax" || extension == "tmLanguage")
+        })
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        match load_grammar(&path) {
+            Ok(grammar) => builder.add(grammar),
+            Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+        }
+    }
+
+    Grammars {
+        set: builder.build(),
+        errors,
+    }
+}
+
+// Read a grammar file. A TextMate grammar is turned into the sublime-syntax format first.
+fn load_grammar(path: &Path) -> Result<SyntaxDefinition, String> {
+    let text = if path
+        .extension()
+        .is_some_and(|extension| extension == "tmLanguage")
+    {
+        let grammar = Value::from_file(path).map_err(|error| error.to_string())?;
+        let grammar = grammar
+            .as_dictionary()
+            .ok_or_else(|| String::from("the grammar is not a dictionary"))?;
+        from_tm_language(grammar)?
+    } else {
+        fs::read_to_string(path).map_err(|error| error.to_string())?
+    };
+
+    let fallback_name = path.file_stem().and_then(|name| name.to_str());
+    SyntaxDefinition::load_from_str(&text, true, fallback_name).map_err(|error| error.to_string())
+}
+
+// Turn a TextMate grammar into a sublime-syntax one, which syntect can read. The repository of the
+// grammar becomes the contexts, a rule with a begin and an end pushes a context of its own, and a
+// rule with only patterns becomes a context that is included.
+fn from_tm_language(grammar: &Dictionary) -> Result<String, String> {
+    let scope = grammar
+        .get("scopeName")
+        .and_then(Value::as_string)
+        .ok_or_else(|| String::from("the grammar has no scopeName"))?;
+
+    let mut converter = Converter::default();
+    let main = converter.patterns(grammar);
+    converter.contexts.insert(String::from("main"), main);
+    if let Some(repository) = grammar.get("repository").and_then(Value::as_dictionary) {
+        for (name, rule) in repository {
+            let context = match rule.as_dictionary() {
+                Some(rule) if rule.contains_key("match") || rule.contains_key("begin") => {
+                    Value::Array(converter.rule(rule).into_iter().collect())
+                }
+                Some(rule) => converter.patterns(rule),
+                None => continue,
+            };
+            converter.contexts.insert(name.clone(), context);
+        }
+    }
+
+    let mut syntax = Dictionary::new();
+    syntax.insert(String::from("scope"), Value::from(scope));
+    if let Some(name) = grammar.get("name") {
+        syntax.insert(String::from("name"), name.clone());
+    }
+    if let Some(file_types) = grammar.get("fileTypes") {
+        syntax.insert(String::from("file_extensions"), file_types.clone());
+    }
+    if let Some(first_line) = grammar.get("firstLineMatch") {
+        syntax.insert(String::from("first_line_match"), first_line.clone());
+    }
+    syntax.insert(
+        String::from("contexts"),
+        Value::Dictionary(converter.contexts),
+    );
+
+    let mut text = String::new();
+    write_yaml(&Value::Dictionary(syntax), &mut text);
+    Ok(text)
+}
+
+#[derive(Default)]
+struct Converter {
+    contexts: Dictionary,
+    anonymous: usize, // The number of contexts made for rules that only have patterns
+}
+
+impl Converter {
+    // Convert the patterns of a grammar or a rule into the rules of a context.
+    fn patterns(&mut self, rule: &Dictionary) -> Value {
+        let patterns = rule.get("patterns").and_then(Value::as_array);
+        Value::Array(
+            patterns
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_dictionary)
+                .filter_map(|pattern| self.rule(pattern))
+                .collect(),
+        )
+    }
+
+    fn rule(&mut self, rule: &Dictionary) -> Option<Value> {
+        let mut result = Dictionary::new();
+        if let Some(include) = rule.get("include").and_then(Value::as_string) {
+            let context = match include {
+                "$self" | "$base" => String::from("main"),
+                _ => match include.strip_prefix('#') {
+                    Some(name) => String::from(name),
+                    None => format!("scope:{}", include),
+                },
+            };
+            result.insert(String::from("include"), Value::from(context));
+        } else if let Some(regex) = rule.get("match") {
+            result.insert(String::from("match"), regex.clone());
+            if let Some(scope) = rule.get("name") {
+                result.insert(String::from("scope"), scope.clone());
+            }
+            insert_captures(&mut result, rule.get("captures"));
+        } else if let Some(begin) = rule.get("begin") {
+            // A 'while' rule goes on as long as its pattern matches at the start of the lines,
+            // which is close to ending at the first line where it doesn't.
+            let end = match (
+                rule.get("end"),
+                rule.get("while").and_then(Value::as_string),
+            ) {
+                (Some(end), _) => end.clone(),
+                (None, Some(pattern)) => Value::from(format!("^(?!{})", pattern)),
+                (None, None) => return None,
+            };
+            let mut context = Vec::new();
+            if let Some(scope) = rule.get("name") {
+                context.push(single("meta_scope", scope.clone()));
+            }
+            if let Some(scope) = rule.get("contentName") {
+                context.push(single("meta_content_scope", scope.clone()));
+            }
+            let mut end_rule = Dictionary::new();
+            end_rule.insert(String::from("match"), end);
+            insert_captures(
+                &mut end_rule,
+                rule.get("endCaptures").or(rule.get("captures")),
+            );
+            end_rule.insert(String::from("pop"), Value::Boolean(true));
+            context.push(Value::Dictionary(end_rule));
+            if let Value::Array(patterns) = self.patterns(rule) {
+                context.extend(patterns);
+            }
+
+            result.insert(String::from("match"), begin.clone());
+            insert_captures(
+                &mut result,
+                rule.get("beginCaptures").or(rule.get("captures")),
+            );
+            result.insert(String::from("push"), Value::Array(context));
+        } else {
+            self.anonymous += 1;
+            let name = format!("anonymous-{}", self.anonymous);
+            let context = self.patterns(rule);
+            self.contexts.insert(name.clone(), context);
+            result.insert(String::from("include"), Value::from(name));
+        }
+        Some(Value::Dictionary(result))
+    }
+}
+
+fn single(key: &str, value: Value) -> Value {
+    let mut dictionary = Dictionary::new();
+    dictionary.insert(String::from(key), value);
+    Value::Dictionary(dictionary)
+}
+
+// Add the scopes of the captured groups of a TextMate rule to a sublime-syntax one.
+fn insert_captures(rule: &mut Dictionary, captures: Option<&Value>) {
+    let captures = match captures.and_then(Value::as_dictionary) {
+        Some(captures) => captures,
+        None => return,
+    };
+    let mut scopes = Dictionary::new();
+    for (group, capture) in captures {
+        let scope = capture
+            .as_dictionary()
+            .and_then(|capture| capture.get("name"));
+        if let Some(scope) = scope {
+            scopes.insert(group.clone(), scope.clone());
+        }
+    }
+    rule.insert(String::from("captures"), Value::Dictionary(scopes));
+}
+
+// Write a value in the flow style of YAML, which is JSON except that the numbers of the captured
+// groups are written as numbers.
+fn write_yaml(value: &Value, out: &mut String) {
+    match value {
+        Value::Dictionary(dictionary) => {
+            out.push('{');
+            for (index, (key, value)) in dictionary.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_digit()) {
+                    out.push_str(key);
+                } else {
+                    write_string(key, out);
+                }
+                out.push_str(": ");
+                write_yaml(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(array) => {
+            out.push('[');
+            for (index, value) in array.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                write_yaml(value, out);
+            }
+            out.push(']');
+        }
+        Value::Boolean(value) => out.push_str(&value.to_string()),
+        Value::Integer(value) => out.push_str(&value.to_string()),
+        Value::Real(value) => out.push_str(&value.to_string()),
+        Value::String(value) => write_string(value, out),
+        _ => out.push_str("null"),
+    }
+}
+
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Example</string>
+    <key>scopeName</key>
+    <string>source.example</string>
+    <key>fileTypes</key>
+    <array><string>ex</string></array>
+    <key>firstLineMatch</key>
+    <string>^#!.*example</string>
+    <key>patterns</key>
+    <array>
+        <dict><key>include</key><string>#comments</string></dict>
+        <dict><key>include</key><string>#values</string></dict>
+        <dict>
+            <key>begin</key><string>\(</string>
+            <key>end</key><string>\)</string>
+            <key>patterns</key>
+            <array><dict><key>include</key><string>$self</string></dict></array>
+        </dict>
+    </array>
+    <key>repository</key>
+    <dict>
+        <key>comments</key>
+        <dict>
+            <key>match</key><string>(--).*$</string>
+            <key>name</key><string>comment.line.example</string>
+            <key>captures</key>
+            <dict>
+                <key>1</key>
+                <dict><key>name</key><string>punctuation.definition.comment</string></dict>
+            </dict>
+        </dict>
+        <key>values</key>
+        <dict>
+            <key>patterns</key>
+            <array>
+                <dict>
+                    <key>match</key><string>\b(if|then)\b</string>
+                    <key>name</key><string>keyword.control.example</string>
+                </dict>
+                <dict>
+                    <key>begin</key><string>"</string>
+                    <key>end</key><string>"</string>
+                    <key>name</key><string>string.quoted.double.example</string>
+                    <key>patterns</key>
+                    <array>
+                        <dict>
+                            <key>match</key><string>\\.</string>
+                            <key>name</key><string>constant.character.escape.example</string>
+                        </dict>
+                    </array>
+                </dict>
+            </array>
+        </dict>
+    </dict>
+</dict>
+</plist>"#;
+
+    fn convert(plist: &str) -> Result<String, String> {
+        let grammar = Value::from_reader_xml(plist.as_bytes()).expect("invalid plist");
+        from_tm_language(grammar.as_dictionary().expect("not a dictionary"))
+    }
+
+    // Parse a line with the converted grammar, returning the text of each span with the innermost
+    // scope it is in.
+    fn spans(line: &str) -> Vec<(String, String)> {
+        let text = convert(GRAMMAR).expect("the grammar was not converted");
+        let definition =
+            SyntaxDefinition::load_from_str(&text, true, None).expect("invalid sublime-syntax");
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(definition);
+        let set = builder.build();
+        let syntax = set.find_syntax_by_name("Example").expect("no syntax");
+
+        let line = format!("{}\n", line);
+        let mut parser = ParseState::new(syntax);
+        let mut scopes = ScopeStack::new();
+        let mut spans = Vec::new();
+        let mut start = 0;
+        for (index, operation) in parser.parse_line(&line, &set).expect("parse failed") {
+            if index > start {
+                let scope = scopes.as_slice().last().map(|s| s.build_string());
+                spans.push((line[start..index].to_string(), scope.unwrap_or_default()));
+                start = index;
+            }
+            scopes.apply(&operation).expect("invalid scope operation");
+        }
+        spans
+    }
+
+    #[test]
+    fn header() {
+        let text = convert(GRAMMAR).expect("the grammar was not converted");
+        let definition =
+            SyntaxDefinition::load_from_str(&text, true, None).expect("invalid sublime-syntax");
+        assert_eq!(definition.name, "Example");
+        assert_eq!(definition.scope.build_string(), "source.example");
+        assert_eq!(definition.file_extensions, ["ex"]);
+        assert_eq!(definition.first_line_match.as_deref(), Some("^#!.*example"));
+    }
+
+    #[test]
+    fn rules() {
+        let spans = spans(r#"if ("a\n") -- done"#);
+        let scope_of = |text: &str| {
+            spans
+                .iter()
+                .find(|(span, _)| span == text)
+                .map(|(_, scope)| scope.as_str())
+        };
+        assert_eq!(scope_of("if"), Some("keyword.control.example"));
+        assert_eq!(scope_of("\"a"), Some("string.quoted.double.example"));
+        assert_eq!(scope_of("\\n"), Some("constant.character.escape.example"));
+        assert_eq!(scope_of("--"), Some("punctuation.definition.comment"));
+        assert_eq!(scope_of(" done"), Some("comment.line.example"));
+    }
+
+    #[test]
+    fn missing_scope_name() {
+        let plist = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict><key>name</key><string>Broken</string></dict></plist>"#;
+        assert!(convert(plist).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::color_depth;
+use crate::filetype;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Error;
+use termion::{color, style};
+
+// The themes built into the editor. A theme with the same name in the user's themes directory
+// replaces the built-in one.
+const BUILT_IN: [(&str, &str); 3] = [
+    ("default", include_str!("../themes/default.toml")),
+    ("light", include_str!("../themes/light.toml")),
+    ("mono", include_str!("../themes/mono.toml")),
+];
+
+// A color written as "#rrggbb".
+#[derive(PartialEq, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    red: u8,
+    green: u8,
+    blue: u8,
+}
+
+// How a piece of text is drawn. The parts that aren't set are taken from the style it is drawn
+// over, which is the style of the plain text in the end.
+#[derive(PartialEq, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: Option<bool>,
+    italic: Option<bool>,
+    underline: Option<bool>,
+    reverse: Option<bool>, // Swap the foreground and the background colors
+}
+
+// A theme as it is written in a theme file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    #[serde(default)]
+    scopes: HashMap<String, Style>,
+}
+
+// A named set of styles for the scopes of the highlighting, such as "comment" or
+// "string.escape", and for the parts of the editor, such as "ui.statusbar".
+pub struct Theme {
+    name: String,
+    scopes: HashMap<String, Style>,
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let digits = text
+            .strip_prefix('#')
+            .filter(|digits| digits.len() == 6 && digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid color '{}', expected '#rrggbb'", text))?;
+        let channel = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).unwrap_or(0);
+        Ok(Color {
+            red: channel(0),
+            green: channel(2),
+            blue: channel(4),
+        })
+    }
+}
+
+impl Style {
+    // Draw this style over another one, keeping the parts of the other one this style doesn't
+    // set.
+    pub fn over(self, below: Style) -> Style {
+        Style {
+            fg: self.fg.or(below.fg),
+            bg: self.bg.or(below.bg),
+            bold: self.bold.or(below.bold),
+            italic: self.italic.or(below.italic),
+            underline: self.underline.or(below.underline),
+            reverse: self.reverse.or(below.reverse),
+        }
+    }
+
+    // Return the escape codes that switch the terminal to this style. The terminal's own colors
+    // are used for the colors the style doesn't set, and the others are turned into the nearest
+    // colors the terminal can show.
+    pub fn escape(&self) -> String {
+        let mut escape = style::Reset.to_string();
+        if let Some(fg) = self.fg {
+            escape.push_str(&color_depth::foreground(fg.rgb()));
+        }
+        if let Some(bg) = self.bg {
+            escape.push_str(&color_depth::background(bg.rgb()));
+        }
+        if self.bold == Some(true) {
+            escape.push_str(style::Bold.as_ref());
+        }
+        if self.italic == Some(true) {
+            escape.push_str(style::Italic.as_ref());
+        }
+        if self.underline == Some(true) {
+            escape.push_str(style::Underline.as_ref());
+        }
+        if self.reverse == Some(true) {
+            escape.push_str(style::Invert.as_ref());
+        }
+        escape
+    }
+}
+
+impl Color {
+    fn rgb(self) -> color::Rgb {
+        color::Rgb(self.red, self.green, self.blue)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let (name, text) = BUILT_IN[0];
+        Theme::parse(name, text).expect("the built-in default theme is invalid")
+    }
+}
+
+impl Theme {
+    // Load the theme with the given name from the user's themes directory, or else from the
+    // built-in themes.
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let user_theme = filetype::config_directory()
+            .map(|directory| directory.join("themes").join(format!("{}.toml", name)))
+            .filter(|path| path.is_file());
+        let text = match user_theme {
+            Some(path) => fs::read_to_string(path)?,
+            None => BUILT_IN
+                .iter()
+                .find(|(built_in, _)| *built_in == name)
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| Error::other(format!("unknown colorscheme: {}", name)))?,
+        };
+        Theme::parse(name, &text)
+    }
+
+    fn parse(name: &str, text: &str) -> Result<Self, Error> {
+        let file: ThemeFile = toml::from_str(text)
+            .map_err(|error| Error::other(format!("{}: {}", name, error.message())))?;
+        Ok(Theme {
+            name: name.to_string(),
+            scopes: file.scopes,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Find the style of a scope. A scope the theme doesn't have takes the style of the scope it
+    // is a part of, so that "string.escape" is drawn as a "string" unless the theme has a style
+    // for it.
+    pub fn style(&self, scope: &str) -> Style {
+        let mut scope = scope;
+        loop {
+            if let Some(style) = self.scopes.get(scope) {
+                return *style;
+            }
+            match scope.rsplit_once('.') {
+                Some((parent, _)) => scope = parent,
+                None => return Style::default(),
+            }
+        }
+    }
+
+    // The style of the plain text, which the other styles are drawn over.
+    pub fn text(&self) -> Style {
+        self.style("text")
+    }
+
+    // The style of a part of the editor, drawn over the plain text.
+    pub fn ui(&self, scope: &str) -> Style {
+        self.style(scope).over(self.text())
+    }
+}